@@ -0,0 +1,45 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// With the `embedded-assets` feature, compiles every file of `assets/` into
+// the binary as a `(name, bytes)` table. The table is empty without the
+// feature or the directory.
+fn main() {
+    let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("assets");
+    // a missing watched path makes cargo rebuild every time, so the
+    // directory is only watched when it is embedded
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=CARGO_FEATURE_EMBEDDED_ASSETS");
+
+    let mut files = Vec::new();
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some() {
+        if !dir.is_dir() {
+            println!("cargo:warning=embedded-assets is on but {} does not exist", dir.display());
+            // the package root changes when the directory is created
+            println!("cargo:rerun-if-changed={}", env::var("CARGO_MANIFEST_DIR").unwrap());
+        }
+        else {
+            println!("cargo:rerun-if-changed={}", dir.display());
+        }
+    }
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_some()
+        && let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                println!("cargo:rerun-if-changed={}", path.display());
+                files.push((entry.file_name().to_string_lossy().into_owned(), path));
+            }
+        }
+    }
+    files.sort();
+
+    let mut out = String::from("pub static EMBEDDED_ASSETS: &[(&str, &[u8])] = &[\n");
+    for (name, path) in files {
+        out += &format!("    ({:?}, include_bytes!({:?})),\n", name, path.display().to_string());
+    }
+    out += "];\n";
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    fs::write(out_path, out).unwrap();
+}
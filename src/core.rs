@@ -7,5 +7,8 @@ pub mod board;
 pub mod token;
 pub mod lib;
 pub mod io;
+pub mod rule;
+pub mod asset;
 pub mod tilepic;
-pub mod tiledata;
\ No newline at end of file
+pub mod tiledata;
+pub mod validate;
\ No newline at end of file
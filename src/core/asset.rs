@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+use photon_rs::PhotonImage;
+use photon_rs::native::{open_image, open_image_from_bytes};
+use crate::core::io::Error;
+
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// Environment variable naming the asset directory.
+pub const ASSET_DIR_VAR: &str = "CACASON_ASSET_DIR";
+
+fn embedded(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED_ASSETS.iter().find(|(n, _)| *n == name).map(|(_, bytes)| *bytes)
+}
+
+/// Where `tiledata.txt` and the tile pictures are loaded from: a directory,
+/// or the copies compiled in with the `embedded-assets` feature when `dir`
+/// is `None`.
+#[derive(Clone, Debug)]
+pub struct AssetRoot {
+    pub dir: Option<PathBuf>,
+}
+
+impl AssetRoot {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        AssetRoot { dir: Some(dir.into()) }
+    }
+    pub fn embedded() -> Self {
+        AssetRoot { dir: None }
+    }
+    /// The configured directory if any, else `CACASON_ASSET_DIR`, else the
+    /// embedded copies if they were compiled in, else `assets` in the
+    /// working directory.
+    pub fn resolve(configured: Option<&Path>) -> Self {
+        if let Some(dir) = configured {
+            return AssetRoot::new(dir);
+        }
+        match std::env::var_os(ASSET_DIR_VAR) {
+            Some(dir) => AssetRoot::new(dir),
+            None if embedded("tiledata.txt").is_some() => AssetRoot::embedded(),
+            None => AssetRoot::new("assets"),
+        }
+    }
+    /// Where a file is looked up, for error messages.
+    fn path(&self, name: &str) -> String {
+        match &self.dir {
+            Some(dir) => dir.join(name).display().to_string(),
+            None => format!("<embedded>/{name}"),
+        }
+    }
+    fn missing(&self, name: &str, reason: impl ToString) -> Error {
+        Error::MissingAsset { path: self.path(name), reason: reason.to_string() }
+    }
+    pub fn read_tile_data(&self) -> Result<String, Error> {
+        let name = "tiledata.txt";
+        match &self.dir {
+            Some(dir) => std::fs::read_to_string(dir.join(name)).map_err(|x| self.missing(name, x)),
+            None => {
+                let bytes = embedded(name).ok_or_else(|| self.missing(name, "not embedded"))?;
+                String::from_utf8(bytes.to_vec()).map_err(|x| self.missing(name, x))
+            }
+        }
+    }
+    pub fn open_picture(&self, picname: &str) -> Result<PhotonImage, Error> {
+        let name = format!("{picname}.png");
+        match &self.dir {
+            Some(dir) => open_image(dir.join(&name)).map_err(|x| self.missing(&name, x)),
+            None => {
+                let bytes = embedded(&name).ok_or_else(|| self.missing(&name, "not embedded"))?;
+                open_image_from_bytes(bytes).map_err(|x| self.missing(&name, x))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "embedded-assets")]
+    #[test]
+    fn embedded_assets_hold_the_tile_data() {
+        assert!(AssetRoot::embedded().read_tile_data().is_ok_and(|data| !data.is_empty()));
+    }
+
+    /// A fresh directory holding `files`.
+    pub fn fixture(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cacason-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, bytes) in files {
+            std::fs::write(dir.join(file), bytes).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn parses_a_fixture_directory() {
+        let dir = fixture("parse", &[("tiledata.txt", b"Pictureabcd\n0 CFFF City u 12 Field else *1 1a start\n")]);
+        let pics = crate::core::tilepic::parse(&AssetRoot::new(&dir)).unwrap();
+        assert_eq!(pics.len(), 1);
+        assert_eq!((pics[0].name.as_str(), pics[0].tiles.len()), ("abcd", 1));
+    }
+
+    #[test]
+    fn missing_tile_data_names_the_file() {
+        let dir = fixture("empty", &[]);
+        match AssetRoot::new(&dir).read_tile_data() {
+            Err(Error::MissingAsset { path, .. }) => assert!(path.ends_with("tiledata.txt"), "{path}"),
+            _ => panic!("read tile data from an empty directory"),
+        }
+        assert!(matches!(AssetRoot::new(&dir).open_picture("abcd"), Err(Error::MissingAsset { path, .. }) if path.ends_with("abcd.png")));
+    }
+
+    #[test]
+    fn resolve_prefers_argument_then_variable_then_embedded() {
+        let fallback = if embedded("tiledata.txt").is_some() { None } else { Some(PathBuf::from("assets")) };
+        // SAFETY: no other test reads or writes the variable
+        unsafe { std::env::remove_var(ASSET_DIR_VAR) };
+        assert_eq!(AssetRoot::resolve(None).dir, fallback);
+        assert_eq!(AssetRoot::resolve(Some(Path::new("given"))).dir, Some(PathBuf::from("given")));
+        unsafe { std::env::set_var(ASSET_DIR_VAR, "from-env") };
+        assert_eq!(AssetRoot::resolve(None).dir, Some(PathBuf::from("from-env")));
+        assert_eq!(AssetRoot::resolve(Some(Path::new("given"))).dir, Some(PathBuf::from("given")));
+        unsafe { std::env::remove_var(ASSET_DIR_VAR) };
+    }
+}
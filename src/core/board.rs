@@ -1,12 +1,43 @@
 use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::rc::Rc;
 use genawaiter::rc::{Gen, Co};
 use crate::core::lib::*;
 use crate::core::io::*;
-use crate::core::player::Player;
-use crate::core::segment::PlacedSegment;
+use crate::core::player::{Player, ScoreCategory, ScoreRecord};
+use crate::core::rule::{FarmRule, Rules, UnplaceableRule};
+use crate::core::segment::{PlacedSegment, SegmentType};
 use crate::core::tile::{PlacedTile, Tile};
-use crate::core::object::Object;
+use crate::core::object::{majority, CanScore, Object, ObjectData, ObjectId, ObjectRegistry, SegKey};
+use crate::core::feature::FeatureType;
+use crate::core::token::{BelongingToken, Goods, PlacedToken, Token};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Init,
+    DrawTile,
+    PlaceTile,
+    PlaceToken { pos: Pos },
+    Score { pos: Pos },
+    FinalScore,
+    End,
+}
+
+/// The mutable part of a `Board`, kept so that actions can be undone.
+#[derive(Clone)]
+pub struct Snapshot {
+    tiles: HashMap<Pos, PlacedTile>,
+    stack: Vec<Tile>,
+    players: Vec<Player>,
+    features: Vec<(Pos, usize)>,
+    registry: ObjectRegistry,
+    discarded: Vec<Tile>,
+    rng: Rng,
+    current_player_id: usize,
+    turn: u32,
+    current_tile: Option<Tile>,
+    phase: Phase,
+}
 
 pub struct Board {
     pub tiles: HashMap<Pos, PlacedTile>,
@@ -14,86 +45,1416 @@ pub struct Board {
     pub players: Vec<Player>,
     pub extension: Rc<ExtensionState>,
 
+    /// `(pos, id)` of the features of every placed tile, in placing order.
+    pub features: Vec<(Pos, usize)>,
+    pub registry: ObjectRegistry,
+    pub rules: Rules,
+    pub discarded: Vec<Tile>,
+    /// Seeds `rng` when the game starts, so that a seed and a set of
+    /// extensions always give the same draw order.
+    pub seed: u64,
+    pub rng: Rng,
+
     pub current_player_id: usize,
+    /// Counts from 1; the placement of the start tile is not a turn.
+    pub turn: u32,
+    pub current_tile: Option<Tile>,
+    pub phase: Phase,
+
+    /// States before each undoable action, oldest first. `UndoScope::Turn`
+    /// entries mark the start of a turn.
+    pub undo_history: Vec<(UndoScope, Snapshot)>,
+    /// For each undo not yet redone, the state it left and the entries it
+    /// took off `undo_history`.
+    pub redo_history: Vec<(Snapshot, Vec<(UndoScope, Snapshot)>)>,
 }
 
 impl Board {
     pub fn create(player_num: usize, extension: ExtensionState) -> Self {
         let extension: Rc<ExtensionState> = Rc::new(extension);
-        Board {
+        let mut board = Board {
             tiles: HashMap::new(),
             stack: Vec::new(),
             players: (0..player_num).map(|x| Player::create(x)).collect(),
             extension: extension,
-            current_player_id: 0
-        }
+            features: Vec::new(),
+            registry: ObjectRegistry::default(),
+            rules: Rules::default(),
+            discarded: Vec::new(),
+            seed: 0,
+            rng: Rng::new(0),
+            current_player_id: 0,
+            turn: 1,
+            current_tile: None,
+            phase: Phase::Init,
+            undo_history: Vec::new(),
+            redo_history: Vec::new()
+        };
+        board.set_seed(RandomState::new().build_hasher().finish());
+        board
+    }
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
     }
     pub fn search_object<'a>(&'a self, seg: &'a PlacedSegment) -> Object<'a> {
-        let pos = seg.pos;
-        let mut occupied_pos: HashSet<(Pos, Dir8)> = HashSet::new();
-        let mut to_search_pos: Vec<(Pos, Dir8)> = Vec::new();
-        for &dir in &seg.direction {
-            occupied_pos.insert((pos + dir.dir, -dir));
-            to_search_pos.push((pos, dir));
-        }
-        let mut obj = Object::create(seg);
-        while to_search_pos.len() > 0 {
-            let mut to_add: Vec<(Pos, Dir8)> = Vec::new();
-            for &(pos, dir) in &to_search_pos {
-                if occupied_pos.contains(&(pos, dir)) { continue; }
-                let next_pos = pos + dir.dir;
-                if let Some(tile) = self.tiles.get(&next_pos) {
-                    if let Some(other_sig) = tile.find_seg(-dir, &seg.typ) {
-                        let _ = obj.push(other_sig);
-                        let mut v: Vec<(Pos, Dir8)> = other_sig.direction.iter()
-                            .map(|x| (next_pos, *x)).collect();
-                        v.iter().for_each(|&(p, d)| { occupied_pos.insert((p + d.dir, -d)); });
-                        to_add.append(&mut v);
-                    }
-                }
-                else {
-                    obj.opened_side.insert((pos, dir));
-                }
-            }
-            to_search_pos = to_add;
-        }
-        obj
+        self.object_view(self.object_of(seg)).unwrap()
+    }
+    fn object_view(&self, id: ObjectId) -> Option<Object<'_>> {
+        let data = self.registry.get(id)?;
+        Some(Object {
+            segments: data.members.iter().map(|&(p, i)| self.segment(p, i).unwrap()).collect(),
+            data
+        })
     }
-    pub fn can_place(&self, tile: Tile, pos: Pos, orient: Spin) -> bool {
+    pub fn object_id(&self, pos: Pos, id: usize) -> Option<ObjectId> {
+        self.registry.id_of((pos, id))
+    }
+    pub fn object_of(&self, seg: &PlacedSegment) -> ObjectId {
+        self.registry.id_of((seg.pos, seg.id)).unwrap()
+    }
+    /// Type, members and completeness of the object `id`.
+    pub fn object(&self, id: ObjectId) -> Option<&ObjectData> {
+        self.registry.get(id)
+    }
+    pub fn object_tokens(&self, id: ObjectId) -> impl Iterator<Item=&PlacedToken> {
+        self.registry.get(id).into_iter().flat_map(|data| self.tokens_on(&data.members))
+    }
+    /// Points the object `id` would score if it were scored now.
+    pub fn object_value(&self, id: ObjectId) -> Option<u32> {
+        self.object_view(id).map(|obj| obj.value())
+    }
+    /// Checks that `tile` can be put at `pos` with `orient`: the position must
+    /// be empty, touch at least one placed tile, match every neighbouring side
+    /// and, for a river tile, continue the river without a U-turn.
+    pub fn check_place(&self, tile: &Tile, pos: Pos, orient: Spin) -> Result<(), PlaceError> {
+        if self.have_tile(pos) { return Err(PlaceError::Occupied) }
+        let mut adjacent = false;
         for diri in 0..4 {
             let dir = Dir4::from_id(diri);
             if let Some(tilep) = self.tiles.get(&(pos + dir)) {
                 if !tile.can_connect(orient, tilep, dir) {
-                    return false
+                    return Err(PlaceError::SideMismatch {
+                        dir,
+                        side: tile.side(orient, dir),
+                        other: tilep.side(-dir)
+                    })
                 }
+                adjacent = true;
+            }
+        }
+        if !adjacent { return Err(PlaceError::NoNeighbour) }
+        self.check_river(tile, pos, orient)
+    }
+    fn check_river(&self, tile: &Tile, pos: Pos, orient: Spin) -> Result<(), PlaceError> {
+        let rivers: Vec<Dir4> = (0..4).map(Dir4::from_id)
+            .filter(|&dir| tile.side(orient, dir) == SideType::River)
+            .collect();
+        if rivers.is_empty() { return Ok(()) }
+        let Some(&entry) = rivers.iter().find(|&&dir| self.have_tile(pos + dir)) else {
+            return Err(PlaceError::RiverNotConnected)
+        };
+        // the river turn through a tile, as a spin from the flow going in to the flow going out
+        let turn = |inward: Dir4, outward: Dir4| (outward.id() + 4 - inward.id()) % 4;
+        let prev = &self.tiles[&(pos + entry)];
+        let prev_entry: Vec<Dir4> = (0..4).map(Dir4::from_id)
+            .filter(|&dir| dir != -entry && prev.side(dir) == SideType::River)
+            .collect();
+        let exit: Vec<&Dir4> = rivers.iter().filter(|&&dir| dir != entry).collect();
+        if let ([prev_entry], [exit]) = (&prev_entry[..], &exit[..]) {
+            let prev_turn = turn(-*prev_entry, -entry);
+            if prev_turn != 0 && prev_turn == turn(-entry, **exit) {
+                return Err(PlaceError::RiverUTurn)
             }
         }
-        true
+        Ok(())
+    }
+    pub fn can_place(&self, tile: &Tile, pos: Pos, orient: Spin) -> bool {
+        self.check_place(tile, pos, orient).is_ok()
+    }
+    /// Empty positions next to at least one placed tile, sorted.
+    pub fn frontier(&self) -> Vec<Pos> {
+        let mut ret: Vec<Pos> = self.tiles.keys()
+            .flat_map(|&pos| (0..4).map(move |i| pos + Dir4::from_id(i)))
+            .filter(|pos| !self.have_tile(*pos))
+            .collect::<HashSet<Pos>>().into_iter().collect();
+        ret.sort();
+        ret
+    }
+    /// Every `(Pos, Spin)` at which `tile` can be placed now.
+    pub fn placements(&self, tile: &Tile) -> Vec<(Pos, Spin)> {
+        self.frontier().into_iter()
+            .flat_map(|pos| (0..4).map(move |i| (pos, Spin::from_id(i))))
+            .filter(|&(pos, spin)| self.can_place(tile, pos, spin))
+            .collect()
+    }
+    pub fn can_place_anywhere(&self, tile: &Tile) -> bool {
+        self.frontier().into_iter()
+            .any(|pos| (0..4).any(|i| self.can_place(tile, pos, Spin::from_id(i))))
     }
     pub fn place(&mut self, tile: Tile, pos: Pos, orient: Spin) {
+        self.features.extend((0..tile.features.len()).map(|id| (pos, id)));
         self.tiles.insert(pos, PlacedTile::create(pos, tile, orient));
+        self.registry.add_tile(&self.tiles, pos);
+    }
+    pub fn segment(&self, pos: Pos, id: usize) -> Option<&PlacedSegment> {
+        self.tiles.get(&pos).and_then(|tile| tile.seg(id))
+    }
+    pub fn tokens_on<'a>(&'a self, keys: &'a [(Pos, usize)]) -> impl Iterator<Item=&'a PlacedToken> {
+        keys.iter().flat_map(|&(p, i)| self.segment(p, i).unwrap().tokens.iter())
+    }
+    /// The segment keys of every object whose type passes `filter`, each
+    /// sorted, ordered by their first key.
+    pub fn objects(&self, filter: impl Fn(&SegmentType) -> bool) -> Vec<Vec<SegKey>> {
+        let mut ret: Vec<Vec<SegKey>> = self.registry.roots()
+            .filter(|(_, data)| filter(&data.typ))
+            .map(|(_, data)| {
+                let mut keys = data.members.clone();
+                keys.sort();
+                keys
+            })
+            .collect();
+        ret.sort();
+        ret
     }
     pub fn have_tile(&self, pos: Pos) -> bool {
         self.tiles.contains_key(&pos)
     }
     pub fn next_player(&mut self) {
-        self.current_player_id = (self.current_player_id + 1) % self.players.len();
+        for _ in 0..self.players.len() {
+            self.current_player_id = (self.current_player_id + 1) % self.players.len();
+            if !self.players[self.current_player_id].resigned { break; }
+        }
+    }
+    pub fn active_players(&self) -> usize {
+        self.players.iter().filter(|player| !player.resigned).count()
+    }
+    /// Returns whether the resigning player is the current one.
+    pub fn resign(&mut self, player_id: usize) -> bool {
+        if let Some(player) = self.players.get_mut(player_id) {
+            player.resigned = true;
+        }
+        player_id == self.current_player_id
+    }
+    /// Gives a token and everything attached to it back to their owners.
+    fn return_token(&mut self, token: PlacedToken) {
+        for belonging in token.belonging {
+            self.players[belonging.player_id].return_belonging(belonging.token);
+        }
+        self.players[token.player_id].return_token(token.token);
+    }
+    pub fn takeback_token_from_segment(&mut self, pos: Pos, id: usize) {
+        let Some(seg) = self.tiles.get_mut(&pos).and_then(|tile| tile.seg_mut(id)) else { return };
+        let tokens: Vec<PlacedToken> = seg.tokens.drain(..).collect();
+        let object_id = self.registry.id_of((pos, id)).unwrap();
+        self.registry.remove_tokens(object_id, tokens.len());
+        for token in tokens {
+            self.return_token(token);
+        }
+    }
+    /// Returns every token on the object `id` to its owner.
+    pub fn takeback_tokens_from_object(&mut self, id: ObjectId) {
+        let Some(data) = self.registry.get(id) else { return };
+        for (p, i) in data.members.clone() {
+            self.takeback_token_from_segment(p, i);
+        }
+    }
+    /// Whether the object or feature of `target` already holds a token;
+    /// `None` if there is no such target.
+    pub fn is_occupied(&self, target: TokenTarget) -> Option<bool> {
+        match target {
+            TokenTarget::Segment { pos, id } => self.object_id(pos, id).map(|id| self.registry.get(id).unwrap().token_count > 0),
+            TokenTarget::Feature { pos, id } => self.tiles.get(&pos).and_then(|tile| tile.feature(id)).map(|f| f.occupied(self)),
+        }
+    }
+    pub fn token_targets(&self, pos: Pos) -> Vec<TokenTarget> {
+        let Some(tile) = self.tiles.get(&pos) else { return Vec::new() };
+        tile.segs().iter().filter(|seg| !seg.typ.is_river())
+            .map(|seg| TokenTarget::Segment { pos, id: seg.id })
+            .chain((0..tile.features().len()).map(|id| TokenTarget::Feature { pos, id }))
+            .filter(|&target| self.rules.join_occupied || self.is_occupied(target) == Some(false))
+            .collect()
+    }
+    pub fn belonging_targets(&self, pos: Pos) -> Vec<(TokenTarget, BelongingToken)> {
+        let Some(tile) = self.tiles.get(&pos) else { return Vec::new() };
+        let player = &self.players[self.current_player_id];
+        let mut ret = Vec::new();
+        for seg in tile.segs() {
+            for token in BelongingToken::ALL {
+                if player.have_belonging(token) && token.can_attach(&seg.typ)
+                    && self.object_tokens(self.object_of(seg)).any(|t| t.player_id == player.id) {
+                    ret.push((TokenTarget::Segment { pos, id: seg.id }, token));
+                }
+            }
+        }
+        ret
+    }
+    pub fn takeback_token_from_feature(&mut self, pos: Pos, id: usize) {
+        let Some(feature) = self.tiles.get_mut(&pos).and_then(|tile| tile.feature_mut(id)) else { return };
+        let tokens: Vec<PlacedToken> = feature.tokens.drain(..).collect();
+        for token in tokens {
+            self.return_token(token);
+        }
+    }
+    pub fn deploy_token(&mut self, target: TokenTarget, token: Token) -> Result<(), Error> {
+        match self.is_occupied(target) {
+            None => return Err(Error::IllegalTokenTarget { target }),
+            Some(true) if !self.rules.join_occupied => return Err(Error::ObjectOccupied { target }),
+            _ => ()
+        }
+        let player = &mut self.players[self.current_player_id];
+        match target {
+            TokenTarget::Segment { pos, id } => {
+                let Some(seg) = self.tiles.get_mut(&pos).and_then(|tile| tile.seg_mut(id)) else {
+                    return Err(Error::IllegalTokenTarget { target });
+                };
+                if seg.typ.is_river() {
+                    return Err(Error::IllegalTokenTarget { target });
+                }
+                seg.tokens.push(player.place_token(token)?);
+                let object_id = self.registry.id_of((pos, id)).unwrap();
+                self.registry.add_tokens(object_id, 1);
+            }
+            TokenTarget::Feature { pos, id } => {
+                let Some(feature) = self.tiles.get_mut(&pos).and_then(|tile| tile.feature_mut(id)) else {
+                    return Err(Error::IllegalTokenTarget { target });
+                };
+                feature.tokens.push(player.place_token(token)?);
+            }
+        }
+        Ok(())
+    }
+    pub fn deploy_belonging(&mut self, target: TokenTarget, token: BelongingToken) -> Result<(), Error> {
+        let TokenTarget::Segment { pos, id } = target else {
+            return Err(Error::IllegalTokenTarget { target });
+        };
+        let Some(seg) = self.tiles.get(&pos).and_then(|tile| tile.seg(id)) else {
+            return Err(Error::IllegalTokenTarget { target });
+        };
+        if !token.can_attach(&seg.typ) {
+            return Err(Error::IllegalTokenTarget { target });
+        }
+        let player_id = self.current_player_id;
+        let Some(&(owner_pos, owner_id)) = self.registry.get(self.object_of(seg)).unwrap().members.iter()
+            .find(|&&(p, i)| self.segment(p, i).unwrap().tokens.iter().any(|t| t.player_id == player_id)) else {
+            return Err(Error::IllegalTokenTarget { target });
+        };
+        let placed = self.players[player_id].place_belonging(token)?;
+        let owner = self.tiles.get_mut(&owner_pos).and_then(|tile| tile.seg_mut(owner_id)).unwrap();
+        owner.tokens.iter_mut().find(|t| t.player_id == player_id).unwrap().belonging.push(placed);
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tiles: self.tiles.clone(),
+            stack: self.stack.clone(),
+            players: self.players.clone(),
+            features: self.features.clone(),
+            registry: self.registry.clone(),
+            discarded: self.discarded.clone(),
+            rng: self.rng.clone(),
+            current_player_id: self.current_player_id,
+            turn: self.turn,
+            current_tile: self.current_tile.clone(),
+            phase: self.phase,
+        }
     }
-    pub fn takeback_token_from_segment(&mut self, segment: &mut PlacedSegment) {
-        
+    /// Puts the board back in the state of `snapshot`, tokens and scores
+    /// included. Resignations stand: a player who left stays out.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        let resigned: Vec<bool> = self.players.iter().map(|player| player.resigned).collect();
+        self.tiles = snapshot.tiles;
+        self.stack = snapshot.stack;
+        self.players = snapshot.players;
+        for (player, resigned) in self.players.iter_mut().zip(resigned) {
+            player.resigned = resigned;
+        }
+        self.features = snapshot.features;
+        self.registry = snapshot.registry;
+        self.discarded = snapshot.discarded;
+        self.rng = snapshot.rng;
+        self.current_player_id = snapshot.current_player_id;
+        self.turn = snapshot.turn;
+        self.current_tile = snapshot.current_tile;
+        self.phase = snapshot.phase;
+    }
+    /// Remembers `snapshot` as the state before an action, or before a turn
+    /// for `UndoScope::Turn`, and drops what is beyond `rules.undo_limit`.
+    fn record(&mut self, scope: UndoScope, snapshot: Snapshot) {
+        if self.rules.undo_limit == 0 { return }
+        if scope == UndoScope::Action {
+            self.redo_history.clear();
+        }
+        // a turn without actions is never undone to
+        if scope == UndoScope::Turn && self.undo_history.last().is_some_and(|(scope, _)| *scope == UndoScope::Turn) {
+            self.undo_history.pop();
+        }
+        self.undo_history.push((scope, snapshot));
+        let actions = |history: &[(UndoScope, Snapshot)]| history.iter().filter(|(scope, _)| *scope == UndoScope::Action).count();
+        while actions(&self.undo_history) > self.rules.undo_limit {
+            let first = self.undo_history.iter().position(|(scope, _)| *scope == UndoScope::Action).unwrap();
+            self.undo_history.drain(..=first);
+        }
+    }
+    fn check_undo_allowed(&self, player_id: usize) -> Result<(), Error> {
+        if self.rules.undo_limit == 0 || (self.rules.undo_host_only && player_id != self.rules.undo_host) {
+            return Err(Error::UndoNotAllowed { player_id });
+        }
+        Ok(())
+    }
+    pub fn undo(&mut self, player_id: usize, scope: UndoScope) -> Result<(), Error> {
+        self.check_undo_allowed(player_id)?;
+        let last_action = self.undo_history.iter().rposition(|(scope, _)| *scope == UndoScope::Action);
+        let point = match scope {
+            UndoScope::Action => last_action,
+            UndoScope::Turn => last_action.and_then(|i| self.undo_history[..i].iter().rposition(|(scope, _)| *scope == UndoScope::Turn)),
+        };
+        let Some(point) = point else { return Err(Error::NothingToUndo) };
+        let entries = self.undo_history.split_off(point);
+        let snapshot = entries[0].1.clone();
+        self.redo_history.push((self.snapshot(), entries));
+        self.restore(snapshot);
+        Ok(())
+    }
+    pub fn redo(&mut self, player_id: usize) -> Result<(), Error> {
+        self.check_undo_allowed(player_id)?;
+        let Some((snapshot, entries)) = self.redo_history.pop() else { return Err(Error::NothingToRedo) };
+        self.undo_history.extend(entries);
+        self.restore(snapshot);
+        Ok(())
     }
 
     pub fn game(&mut self) -> Gen<Output, Input, impl Future<Output=()>> {
         Gen::new(|co| async move {
-            self.init(&co).await;
-            self.draw_tile(&co).await;
+            loop {
+                match self.phase {
+                    Phase::Init => self.init(),
+                    Phase::DrawTile => self.draw_tile(&co).await,
+                    Phase::PlaceTile => self.place_tile(&co).await,
+                    Phase::PlaceToken { pos } => self.place_token(&co, pos).await,
+                    Phase::Score { pos } => self.score(&co, pos).await,
+                    Phase::FinalScore => self.final_score(&co).await,
+                    Phase::End => break,
+                }
+            }
         })
     }
-    pub async fn init(&mut self, co: &Co<Output, Input>) {
-        let ret = co.yield_(Output::Nothing).await;
+    /// Yields `output` until an answer other than a resignation arrives.
+    /// Returns `None` if the current player resigned instead. Undo and redo
+    /// are applied here and handed back, so the caller should return at once
+    /// and let `game` resume from the restored phase.
+    async fn ask(&mut self, co: &Co<Output, Input>, output: Output) -> Option<Input> {
+        let mut prompt = output.clone();
+        loop {
+            let input = co.yield_(prompt).await;
+            prompt = output.clone();
+            let result = match input {
+                Input::Resign { player_id } => {
+                    if self.resign(player_id) { return None; }
+                    continue;
+                }
+                Input::Undo { player_id, scope } => self.undo(player_id, scope),
+                Input::Redo { player_id } => self.redo(player_id),
+                input => return Some(input),
+            };
+            match result {
+                Ok(()) => return Some(input),
+                Err(err) => prompt = Output::Error { err },
+            }
+        }
     }
+    pub fn init(&mut self) {
+        for player in &mut self.players {
+            player.init_tokens(&self.extension);
+        }
+        self.rng = Rng::new(self.seed);
+        let start = self.arrange_stack();
+        if let Some(tile) = start.or_else(|| self.stack.pop()) {
+            self.place(tile, Pos::new(0, 0), Spin::No);
+        }
+        self.phase = Phase::DrawTile;
+        self.record(UndoScope::Turn, self.snapshot());
+    }
+    /// Takes the start tile out of the stack and orders the rest to be drawn
+    /// from the end: with the river, its tiles come first and end with the
+    /// lake, then the others. Each group is shuffled with `rng`; the river
+    /// source is the start tile, and the base start tile is shuffled in.
+    fn arrange_stack(&mut self) -> Option<Tile> {
+        let mut tiles = std::mem::take(&mut self.stack);
+        tiles.sort_by(|a, b| a.serial.cmp(&b.serial));
+        let river = self.extension.is_enabled(Extension::Ex0River);
+        let (mut rivers, mut rest): (Vec<Tile>, Vec<Tile>) = tiles.into_iter().partition(|tile| river && tile.is_river());
+        let source = rivers.iter().position(|tile| tile.start && tile.river_ends() == 1)
+            .or_else(|| rivers.iter().position(|tile| tile.river_ends() == 1))
+            .map(|i| rivers.remove(i));
+        let start = source.or_else(|| rest.iter().position(|tile| tile.start).map(|i| rest.remove(i)));
+        let lake = rivers.iter().position(|tile| tile.river_ends() == 1).map(|i| rivers.remove(i));
+        self.rng.shuffle(&mut rest);
+        self.rng.shuffle(&mut rivers);
+        self.stack = rest;
+        self.stack.extend(lake);
+        self.stack.extend(rivers);
+        start
+    }
+    /// Puts an unplaceable tile back at a random place among the tiles of
+    /// its kind, so that the river is still drawn first and ends with the
+    /// lake. Never on top, where it would be drawn again at once.
+    fn reshuffle(&mut self, tile: Tile) {
+        let first_river = self.stack.iter().position(|tile| tile.is_river()).unwrap_or(self.stack.len());
+        let (low, high) = if tile.is_river() {
+            let has_lake = self.stack.get(first_river).is_some_and(|tile| tile.river_ends() == 1);
+            (first_river + has_lake as usize, self.stack.len())
+        }
+        else {
+            (0, first_river)
+        };
+        let low = low.min(self.stack.len().saturating_sub(1));
+        let i = if high > low { low + self.rng.below(high - low) } else { low };
+        self.stack.insert(i, tile);
+    }
+    /// Draws until a placeable tile comes up. Unplaceable tiles are discarded
+    /// or shuffled back according to `rules.unplaceable`; reshuffling falls
+    /// back to discarding once no tile left in the stack fits.
     pub async fn draw_tile(&mut self, co: &Co<Output, Input>) {
-        let ret = co.yield_(Output::Nothing).await;
+        while let Some(tile) = self.stack.pop() {
+            if self.can_place_anywhere(&tile) {
+                self.current_tile = Some(tile);
+                self.phase = Phase::PlaceTile;
+                return;
+            }
+            let serial = tile.serial.clone();
+            if self.rules.unplaceable == UnplaceableRule::Reshuffle
+                && self.stack.iter().any(|tile| self.can_place_anywhere(tile)) {
+                self.reshuffle(tile);
+                co.yield_(Output::TileReshuffled { serial }).await;
+            }
+            else {
+                self.discarded.push(tile);
+                co.yield_(Output::TileDiscarded { serial }).await;
+            }
+        }
+        self.phase = Phase::FinalScore;
+    }
+    pub async fn place_tile(&mut self, co: &Co<Output, Input>) {
+        let Some(tile) = &self.current_tile else {
+            self.phase = Phase::DrawTile;
+            return;
+        };
+        let mut output = Output::AskPlaceTile {
+            player_id: self.current_player_id,
+            serial: tile.serial.clone(),
+            choices: self.placements(tile),
+        };
+        loop {
+            match self.ask(co, output).await {
+                None => {
+                    self.stack.extend(self.current_tile.take());
+                    self.end_turn();
+                    return;
+                }
+                Some(Input::Undo { .. } | Input::Redo { .. }) => return,
+                Some(Input::PlaceTile { pos, spin }) => {
+                    match self.check_place(self.current_tile.as_ref().unwrap(), pos, spin) {
+                        Ok(()) => {
+                            self.record(UndoScope::Action, self.snapshot());
+                            let tile = self.current_tile.take().unwrap();
+                            self.place(tile, pos, spin);
+                            self.phase = Phase::PlaceToken { pos };
+                            return;
+                        }
+                        Err(reason) => output = Output::Error { err: Error::IllegalPlacement { pos, spin, reason } }
+                    }
+                }
+                Some(_) => output = Output::Error { err: Error::InvalidInput }
+            }
+        }
+    }
+    pub async fn place_token(&mut self, co: &Co<Output, Input>, pos: Pos) {
+        let player = &self.players[self.current_player_id];
+        let tokens: Vec<Token> = Token::ALL.into_iter().filter(|&t| player.have_token(t)).collect();
+        let mut output = Output::AskPlaceToken {
+            player_id: self.current_player_id,
+            pos,
+            targets: if tokens.is_empty() { Vec::new() } else { self.token_targets(pos) },
+            tokens,
+            belongings: self.belonging_targets(pos),
+        };
+        loop {
+            let before = self.snapshot();
+            let result = match self.ask(co, output).await {
+                None => break,
+                Some(Input::Undo { .. } | Input::Redo { .. }) => return,
+                Some(Input::SkipToken) => Ok(()),
+                Some(Input::PlaceToken { target, token }) => {
+                    if target.pos() == pos { self.deploy_token(target, token) }
+                    else { Err(Error::IllegalTokenTarget { target }) }
+                }
+                Some(Input::PlaceBelonging { target, token }) => {
+                    if target.pos() == pos { self.deploy_belonging(target, token) }
+                    else { Err(Error::IllegalTokenTarget { target }) }
+                }
+                Some(_) => Err(Error::InvalidInput)
+            };
+            match result {
+                Ok(()) => {
+                    self.record(UndoScope::Action, before);
+                    break;
+                }
+                Err(err) => output = Output::Error { err },
+            }
+        }
+        self.phase = Phase::Score { pos };
+    }
+    pub fn pay(&mut self, event: &ScoreEvent) {
+        for &player_id in &event.player_ids {
+            self.players[player_id].add_score(ScoreRecord {
+                category: event.category,
+                points: event.points,
+                turn: self.turn,
+                object: event.object,
+                object_id: event.object_id
+            });
+        }
+    }
+    /// Pays the object `id` to its token majority and returns the tokens to
+    /// their owners.
+    pub fn score_object(&mut self, id: ObjectId) -> ScoreEvent {
+        let obj = self.object_view(id).unwrap();
+        let (pos, seg_id) = obj.segments.iter().map(|seg| (seg.pos, seg.id)).min().unwrap();
+        let event = ScoreEvent {
+            player_ids: obj.winners(self),
+            points: obj.value(),
+            category: if obj.typ().is_city() { ScoreCategory::City } else { ScoreCategory::Road },
+            object: Some(TokenTarget::Segment { pos, id: seg_id }),
+            object_id: Some(self.registry.find(id))
+        };
+        self.takeback_tokens_from_object(id);
+        self.pay(&event);
+        event
+    }
+    pub fn score_feature(&mut self, pos: Pos, id: usize) -> ScoreEvent {
+        let feature = self.tiles[&pos].feature(id).unwrap();
+        let event = ScoreEvent {
+            player_ids: feature.winners(self),
+            points: feature.value(self),
+            category: match feature.typ {
+                FeatureType::Monastry => ScoreCategory::Monastery,
+            },
+            object: Some(TokenTarget::Feature { pos, id }),
+            object_id: None
+        };
+        self.takeback_token_from_feature(pos, id);
+        self.pay(&event);
+        event
+    }
+    /// Scores the objects completed by the tile at `pos`.
+    pub async fn score(&mut self, co: &Co<Output, Input>, pos: Pos) {
+        let mut events = Vec::new();
+        let mut goods = Vec::new();
+        let mut ids: Vec<ObjectId> = self.tiles[&pos].segs().iter()
+            .filter(|seg| seg.typ.is_road() || seg.typ.is_city())
+            .map(|seg| self.object_of(seg)).collect();
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            let data = self.registry.get(id).unwrap();
+            if !data.complete() { continue; }
+            let occupied = data.token_count > 0;
+            if self.extension.is_enabled(Extension::Ex2Goods) {
+                goods.append(&mut self.object_view(id).unwrap().goods());
+            }
+            if occupied {
+                events.push(self.score_object(id));
+            }
+        }
+        let mut near = pos.around();
+        near.push(pos);
+        let features: Vec<(Pos, usize)> = self.features.iter().filter(|(p, _)| near.contains(p)).cloned().collect();
+        for (p, id) in features {
+            let feature = self.tiles[&p].feature(id).unwrap();
+            if feature.complete(self) && feature.occupied(self) {
+                events.push(self.score_feature(p, id));
+            }
+        }
+        if !goods.is_empty() {
+            let player = &mut self.players[self.current_player_id];
+            for &good in &goods {
+                *player.goods.entry(good).or_insert(0) += 1;
+            }
+            co.yield_(Output::GoodsCollected { player_id: player.id, goods }).await;
+        }
+        if !events.is_empty() {
+            co.yield_(Output::Scored { events }).await;
+        }
+        self.end_turn();
+    }
+    pub fn end_turn(&mut self) {
+        if self.active_players() < self.players.len().min(2) {
+            self.phase = Phase::FinalScore;
+        }
+        else {
+            self.next_player();
+            self.turn += 1;
+            self.phase = Phase::DrawTile;
+            self.record(UndoScope::Turn, self.snapshot());
+        }
+    }
+    /// Pays the farmers. Each field is matched with the completed cities its
+    /// segments are adjacent to, following `rules.farm`.
+    pub fn score_farms(&mut self) -> Vec<ScoreEvent> {
+        let fields: Vec<Vec<(Pos, usize)>> = self.objects(|typ| typ.is_field()).into_iter()
+            .filter(|keys| keys.iter().any(|&(p, i)| self.segment(p, i).unwrap().occupied()))
+            .collect();
+        let mut city_of: HashMap<(Pos, usize), Option<(Pos, usize)>> = HashMap::new();
+        for keys in self.objects(|typ| typ.is_city()) {
+            let complete = self.object(self.object_id(keys[0].0, keys[0].1).unwrap()).unwrap().complete();
+            for &key in &keys {
+                city_of.insert(key, if complete { Some(keys[0]) } else { None });
+            }
+        }
+        let adj_cities: Vec<Vec<(Pos, usize)>> = fields.iter().map(|keys| {
+            let mut cities: Vec<(Pos, usize)> = keys.iter()
+                .flat_map(|&(p, i)| match &self.segment(p, i).unwrap().typ {
+                    SegmentType::FieldSegment { adj_city } => adj_city.iter().filter_map(|&c| city_of.get(&(p, c)).copied().flatten()).collect(),
+                    _ => Vec::new()
+                })
+                .collect::<HashSet<_>>().into_iter().collect();
+            cities.sort();
+            cities
+        }).collect();
+
+        let mut events = Vec::new();
+        match self.rules.farm {
+            FarmRule::CurrentEdition => {
+                for (keys, cities) in fields.iter().zip(&adj_cities) {
+                    if cities.is_empty() { continue; }
+                    events.push(ScoreEvent {
+                        player_ids: majority(self.tokens_on(keys)),
+                        points: 3 * cities.len() as u32,
+                        category: ScoreCategory::Farm,
+                        object: Some(TokenTarget::Segment { pos: keys[0].0, id: keys[0].1 }),
+                        object_id: self.object_id(keys[0].0, keys[0].1)
+                    });
+                }
+            }
+            FarmRule::FirstEdition => {
+                let mut cities: Vec<(Pos, usize)> = adj_cities.iter().flatten().copied().collect::<HashSet<_>>().into_iter().collect();
+                cities.sort();
+                for city in cities {
+                    let player_ids = majority(fields.iter().zip(&adj_cities)
+                        .filter(|(_, adj)| adj.contains(&city))
+                        .flat_map(|(keys, _)| self.tokens_on(keys)));
+                    events.push(ScoreEvent {
+                        player_ids,
+                        points: 4,
+                        category: ScoreCategory::Farm,
+                        object: Some(TokenTarget::Segment { pos: city.0, id: city.1 }),
+                        object_id: self.object_id(city.0, city.1)
+                    });
+                }
+            }
+        }
+        for event in &events {
+            self.pay(event);
+        }
+        for &(p, i) in fields.iter().flatten() {
+            self.takeback_token_from_segment(p, i);
+        }
+        events
+    }
+    /// Ten points to every player holding the most of each kind of goods.
+    pub fn score_goods(&mut self) -> Vec<ScoreEvent> {
+        let mut events = Vec::new();
+        for good in Goods::ALL {
+            let count = |player: &Player| player.goods.get(&good).copied().unwrap_or(0);
+            let max = self.players.iter().map(count).max().unwrap_or(0);
+            if max == 0 { continue; }
+            let event = ScoreEvent {
+                player_ids: self.players.iter().filter(|&p| count(p) == max).map(|p| p.id).collect(),
+                points: 10,
+                category: ScoreCategory::Goods,
+                object: None,
+                object_id: None
+            };
+            self.pay(&event);
+            events.push(event);
+        }
+        events
+    }
+    /// Players ordered by score, then by goods held; resigned players last.
+    /// Players equal on all of these share a rank.
+    pub fn standings(&self) -> Vec<Standing> {
+        let key = |player: &Player| (player.resigned, std::cmp::Reverse(player.score), std::cmp::Reverse(player.goods.values().sum::<u32>()));
+        let mut players: Vec<&Player> = self.players.iter().collect();
+        players.sort_by_key(|&p| (key(p), p.id));
+        let mut ret: Vec<Standing> = Vec::new();
+        for (i, &player) in players.iter().enumerate() {
+            let rank = if i > 0 && key(players[i - 1]) == key(player) { ret[i - 1].rank } else { i + 1 };
+            ret.push(Standing { player_id: player.id, score: player.score, rank });
+        }
+        ret
+    }
+    /// Scores every object still holding tokens at its end-of-game value,
+    /// then farms and goods, and announces the standings.
+    pub async fn final_score(&mut self, co: &Co<Output, Input>) {
+        let mut events = Vec::new();
+        for keys in self.objects(|typ| typ.is_road() || typ.is_city()) {
+            let id = self.object_id(keys[0].0, keys[0].1).unwrap();
+            if self.object(id).unwrap().token_count > 0 {
+                events.push(self.score_object(id));
+            }
+        }
+        for (pos, id) in self.features.clone() {
+            if self.tiles[&pos].feature(id).unwrap().occupied(self) {
+                events.push(self.score_feature(pos, id));
+            }
+        }
+        events.append(&mut self.score_farms());
+        if self.extension.is_enabled(Extension::Ex2Goods) {
+            events.append(&mut self.score_goods());
+        }
+        if !events.is_empty() {
+            co.yield_(Output::Scored { events }).await;
+        }
+        co.yield_(Output::GameEnd { standings: self.standings() }).await;
+        self.phase = Phase::End;
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genawaiter::GeneratorState;
+    use photon_rs::PhotonImage;
+    use crate::core::feature::Feature;
+    use crate::core::segment::Segment;
+    use crate::core::tiledata::SerialNumber;
+    use crate::core::tilepic::Hint;
+
+    fn sides(s: &str) -> [SideType; 4] {
+        std::array::from_fn(|i| match s.as_bytes()[i] {
+            b'C' => SideType::City,
+            b'R' => SideType::Road,
+            b'S' => SideType::River,
+            _ => SideType::Field,
+        })
+    }
+    /// A tile with sides given as "CRFS" letters, up, right, down, left.
+    fn tile(id: u8, side: &str, segments: Vec<Segment>) -> Tile {
+        Tile {
+            start: false,
+            serial: SerialNumber { packid: 0, picname: "test".to_string(), id, sub_id: 0 },
+            sides: sides(side),
+            img: Rc::new(PhotonImage::new(vec![0; 4], 1, 1)),
+            segments,
+            features: Vec::new(),
+        }
+    }
+    fn halves(dirs: &[Dir4]) -> Vec<Dir8> {
+        dirs.iter().flat_map(|&dir| [Dir8::new(dir, true), Dir8::new(dir, false)]).collect()
+    }
+    fn segment(typ: SegmentType, direction: Vec<Dir8>) -> Segment {
+        Segment { typ, direction, hint: Hint::default() }
+    }
+    fn city(dirs: &[Dir4]) -> Segment {
+        segment(SegmentType::CitySegment { pennant: 0, goods: Vec::new() }, halves(dirs))
+    }
+    fn road(dirs: &[Dir4]) -> Segment {
+        segment(SegmentType::RoadSegment { adj_road_city: Vec::new() }, halves(dirs))
+    }
+    fn field(direction: Vec<Dir8>, adj_city: Vec<usize>) -> Segment {
+        segment(SegmentType::FieldSegment { adj_city }, direction)
+    }
+    fn deploy(board: &mut Board, player_id: usize, target: TokenTarget) {
+        board.current_player_id = player_id;
+        board.deploy_token(target, Token::Meeple).unwrap();
+    }
+    /// A start tile with a city on its right, then tiles that close it.
+    fn city_board(undo_limit: usize) -> Board {
+        let mut board = board();
+        board.rules.undo_limit = undo_limit;
+        let mut start = tile(0, "FCFF", vec![city(&[Dir4::Right])]);
+        start.start = true;
+        board.stack.push(start);
+        for id in 1..4 {
+            board.stack.push(tile(id, "FFFC", vec![city(&[Dir4::Left])]));
+        }
+        board
+    }
+    /// Resumes the game from the current phase, answers with `inputs` and
+    /// returns the last output.
+    fn play(board: &mut Board, inputs: Vec<Input>) -> Output {
+        let mut game = board.game();
+        let mut last = game.resume_with(Input::Nothing);
+        for input in inputs {
+            last = game.resume_with(input);
+        }
+        match last {
+            GeneratorState::Yielded(output) => output,
+            GeneratorState::Complete(()) => panic!("the game ended"),
+        }
+    }
+    fn board() -> Board {
+        let mut board = Board::create(2, ExtensionState { enabled: HashSet::new() });
+        board.set_seed(1);
+        for player in &mut board.players {
+            player.init_tokens(&board.extension);
+        }
+        board
+    }
+
+    #[test]
+    fn reshuffled_tile_is_never_drawn_next() {
+        for seed in 0..200 {
+            let mut b = board();
+            b.set_seed(seed);
+            b.stack = (0..3).map(|id| tile(id, "FFFF", Vec::new())).collect();
+            b.reshuffle(tile(9, "FFFF", Vec::new()));
+            assert_ne!(b.stack.last().unwrap().serial.id, 9);
+            assert_eq!(b.stack.len(), 4);
+
+            // a river tile goes above the lake but not on top
+            b.stack = vec![tile(0, "FFFF", Vec::new()), tile(1, "SFFF", Vec::new()), tile(2, "SFSF", Vec::new())];
+            b.reshuffle(tile(9, "SFSF", Vec::new()));
+            let ids: Vec<u8> = b.stack.iter().map(|t| t.serial.id).collect();
+            assert_eq!(ids, vec![0, 1, 9, 2]);
+        }
+    }
+
+    /// Plain tiles 10 to 19 with the base start tile 10, and river tiles 0
+    /// to 5: the source 0, the lake 5 and the middle in between.
+    fn tiles_for_stack() -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = (10..20).map(|id| tile(id, "FFFF", Vec::new())).collect();
+        tiles[0].start = true;
+        let mut source = tile(0, "SFFF", Vec::new());
+        source.start = true;
+        tiles.push(source);
+        tiles.extend((1..5).map(|id| tile(id, "SFSF", Vec::new())));
+        tiles.push(tile(5, "FFSF", Vec::new()));
+        tiles
+    }
+    /// The start tile and the ids of the stack in drawing order.
+    fn arrange(seed: u64, river: bool, tiles: Vec<Tile>) -> (Option<u8>, Vec<u8>) {
+        let enabled = if river { HashSet::from([Extension::Ex0River]) } else { HashSet::new() };
+        let mut b = Board::create(2, ExtensionState { enabled });
+        b.set_seed(seed);
+        b.stack = tiles;
+        let start = b.arrange_stack().map(|tile| tile.serial.id);
+        (start, b.stack.iter().rev().map(|tile| tile.serial.id).collect())
+    }
+
+    #[test]
+    fn seed_and_extensions_fix_the_draw_order() {
+        for seed in 0..20 {
+            for river in [false, true] {
+                let order = arrange(seed, river, tiles_for_stack());
+                assert_eq!(arrange(seed, river, tiles_for_stack()), order);
+                let mut reversed = tiles_for_stack();
+                reversed.reverse();
+                assert_eq!(arrange(seed, river, reversed), order);
+            }
+        }
+        let orders: HashSet<Vec<u8>> = (0..20).map(|seed| arrange(seed, false, tiles_for_stack()).1).collect();
+        assert!(orders.len() > 1);
+    }
+
+    #[test]
+    fn river_is_drawn_first_and_ends_with_the_lake() {
+        for seed in 0..20 {
+            let (start, order) = arrange(seed, true, tiles_for_stack());
+            assert_eq!(start, Some(0));
+            let mut middle = order[..4].to_vec();
+            middle.sort();
+            assert_eq!(middle, vec![1, 2, 3, 4]);
+            assert_eq!(order[4], 5);
+            let mut rest = order[5..].to_vec();
+            rest.sort();
+            assert_eq!(rest, (10..20).collect::<Vec<u8>>());
+
+            // without the river, the base start tile starts
+            let (start, order) = arrange(seed, false, tiles_for_stack().into_iter().take(10).collect());
+            assert_eq!(start, Some(10));
+            assert_eq!(order.len(), 9);
+        }
+    }
+
+    /// The flood fill the registry replaced: the members and open sides of
+    /// the object containing `key`.
+    fn bfs(board: &Board, key: SegKey) -> (HashSet<SegKey>, HashSet<(Pos, Dir8)>) {
+        let typ = &board.segment(key.0, key.1).unwrap().typ;
+        let mut members = HashSet::from([key]);
+        let mut opened = HashSet::new();
+        let mut todo = vec![key];
+        while let Some((pos, id)) = todo.pop() {
+            for &dir in &board.segment(pos, id).unwrap().direction {
+                let next_pos = pos + dir.dir;
+                match board.tiles.get(&next_pos) {
+                    None => { opened.insert((pos, dir)); }
+                    Some(tile) => {
+                        if let Some(other) = tile.find_seg(-dir, typ) && members.insert((next_pos, other.id)) {
+                            todo.push((next_pos, other.id));
+                        }
+                    }
+                }
+            }
+        }
+        (members, opened)
+    }
+    fn assert_matches_bfs(board: &Board) {
+        let mut seen = HashSet::new();
+        for (_, data) in board.registry.roots() {
+            let (members, opened) = bfs(board, data.members[0]);
+            assert_eq!(data.members.iter().copied().collect::<HashSet<_>>(), members);
+            assert_eq!(data.members.len(), members.len());
+            assert_eq!(data.opened_side, opened);
+            assert_eq!(data.tiles, members.iter().map(|&(p, _)| p).collect());
+            for key in members {
+                assert!(seen.insert(key), "{key:?} is in two objects");
+            }
+        }
+        let all: usize = board.tiles.values().map(|tile| tile.segs().len()).sum();
+        assert_eq!(seen.len(), all);
+    }
+
+    #[test]
+    fn registry_merges_through_two_half_sides() {
+        let mut b = board();
+        b.place(tile(0, "FFFF", vec![field(vec![Dir8::new(Dir4::Right, true)], vec![]), field(vec![Dir8::new(Dir4::Right, false)], vec![])]), Pos::new(0, 0), Spin::No);
+        assert_eq!(b.objects(|typ| typ.is_field()).len(), 2);
+        assert_matches_bfs(&b);
+        b.place(tile(1, "FFFF", vec![field(halves(&[Dir4::Left]), vec![])]), Pos::new(1, 0), Spin::No);
+        assert_matches_bfs(&b);
+        let fields = b.objects(|typ| typ.is_field());
+        assert_eq!(fields, vec![vec![(Pos::new(0, 0), 0), (Pos::new(0, 0), 1), (Pos::new(1, 0), 0)]]);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert_eq!(b.object_id(Pos::new(0, 0), 1), Some(id));
+        assert!(b.object(id).unwrap().opened_side.is_empty());
+        assert!(!b.object(id).unwrap().complete());
+    }
+
+    #[test]
+    fn registry_closes_a_city() {
+        let mut b = board();
+        b.place(tile(0, "FCFF", vec![city(&[Dir4::Right])]), Pos::new(0, 0), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert!(!b.object(id).unwrap().complete());
+        assert_eq!(b.object(id).unwrap().opened_side.len(), 2);
+        assert_matches_bfs(&b);
+        b.place(tile(1, "FFFC", vec![city(&[Dir4::Left])]), Pos::new(1, 0), Spin::No);
+        assert_matches_bfs(&b);
+        assert!(b.object(id).unwrap().complete());
+        assert_eq!(b.object(id).unwrap().tiles.len(), 2);
+        assert_eq!(b.object_value(id), Some(4));
+    }
+
+    #[test]
+    fn registry_closes_a_loop_road() {
+        let mut b = board();
+        let corners = [
+            (Pos::new(0, 0), [Dir4::Right, Dir4::Down]),
+            (Pos::new(1, 0), [Dir4::Left, Dir4::Down]),
+            (Pos::new(1, 1), [Dir4::Left, Dir4::Up]),
+            (Pos::new(0, 1), [Dir4::Right, Dir4::Up]),
+        ];
+        for (i, (pos, dirs)) in corners.into_iter().enumerate() {
+            let id = b.object_id(Pos::new(0, 0), 0);
+            assert!(id.is_none_or(|id| !b.object(id).unwrap().complete()));
+            b.place(tile(i as u8, "FFFF", vec![road(&dirs)]), pos, Spin::No);
+            assert_matches_bfs(&b);
+        }
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        let data = b.object(id).unwrap();
+        assert!(data.complete());
+        assert_eq!(data.members.len(), 4);
+        assert_eq!(b.object_value(id), Some(4));
+        assert_eq!(b.objects(|typ| typ.is_road()).len(), 1);
+    }
+
+    const PLACE: Input = Input::PlaceTile { pos: Pos { x: 1, y: 0 }, spin: Spin::No };
+    const UNDO: Input = Input::Undo { player_id: 0, scope: UndoScope::Action };
+
+    #[test]
+    fn undo_puts_the_tile_back_in_hand() {
+        let mut b = city_board(5);
+        let Output::AskPlaceTile { serial, .. } = play(&mut b, vec![]) else { panic!() };
+        let output = play(&mut b, vec![PLACE, UNDO]);
+        assert!(matches!(output, Output::AskPlaceTile { serial: ref s, .. } if *s == serial));
+        assert_eq!(b.phase, Phase::PlaceTile);
+        assert!(b.current_tile.as_ref().is_some_and(|tile| tile.serial == serial));
+        assert_eq!(b.tiles.len(), 1);
+        assert_eq!(b.registry.roots().count(), 1);
+    }
+
+    #[test]
+    fn undo_takes_back_a_score() {
+        let mut b = city_board(5);
+        let meeples = 7;
+        let target = TokenTarget::Segment { pos: Pos::new(1, 0), id: 0 };
+        let output = play(&mut b, vec![PLACE, Input::PlaceToken { target, token: Token::Meeple }, Input::Nothing]);
+        assert!(matches!(output, Output::AskPlaceTile { player_id: 1, .. }));
+        assert_eq!(b.players[0].score, 4);
+        assert_eq!(b.players[0].history.len(), 1);
+        assert_eq!(b.players[0].tokens[&Token::Meeple], meeples);
+
+        let output = play(&mut b, vec![UNDO]);
+        assert!(matches!(output, Output::AskPlaceToken { player_id: 0, .. }));
+        assert_eq!(b.players[0].score, 0);
+        assert!(b.players[0].history.is_empty());
+        assert_eq!(b.players[0].tokens[&Token::Meeple], meeples);
+        assert!(!b.segment(Pos::new(1, 0), 0).unwrap().occupied());
+        assert_eq!(b.tiles.len(), 2);
+
+        let output = play(&mut b, vec![Input::Undo { player_id: 0, scope: UndoScope::Turn }]);
+        assert!(matches!(output, Output::AskPlaceTile { player_id: 0, .. }));
+        assert_eq!(b.tiles.len(), 1);
+        assert_eq!(b.turn, 1);
+    }
+
+    #[test]
+    fn undo_limit_drops_the_oldest_actions() {
+        let mut b = city_board(1);
+        play(&mut b, vec![PLACE, Input::SkipToken]);
+        assert_eq!(b.undo_history.iter().filter(|(scope, _)| *scope == UndoScope::Action).count(), 1);
+        let output = play(&mut b, vec![UNDO]);
+        assert!(matches!(output, Output::AskPlaceToken { player_id: 0, .. }));
+        let output = play(&mut b, vec![UNDO]);
+        assert!(matches!(output, Output::Error { err: Error::NothingToUndo }));
+        assert_eq!(b.tiles.len(), 2);
+
+        let mut b = city_board(0);
+        let output = play(&mut b, vec![PLACE, UNDO]);
+        assert!(matches!(output, Output::Error { err: Error::UndoNotAllowed { player_id: 0 } }));
+        assert!(b.undo_history.is_empty());
+    }
+
+    #[test]
+    fn new_action_clears_redo() {
+        let mut b = city_board(5);
+        play(&mut b, vec![PLACE, UNDO]);
+        assert_eq!(b.redo_history.len(), 1);
+        let output = play(&mut b, vec![Input::Redo { player_id: 0 }]);
+        assert!(matches!(output, Output::AskPlaceToken { player_id: 0, .. }));
+        assert_eq!(b.tiles.len(), 2);
+
+        play(&mut b, vec![UNDO]);
+        let output = play(&mut b, vec![Input::PlaceTile { pos: Pos::new(0, -1), spin: Spin::No }]);
+        assert!(matches!(output, Output::AskPlaceToken { player_id: 0, .. }));
+        assert!(b.redo_history.is_empty());
+        let output = play(&mut b, vec![Input::Redo { player_id: 0 }]);
+        assert!(matches!(output, Output::Error { err: Error::NothingToRedo }));
+    }
+
+    #[test]
+    fn undo_keeps_resignations() {
+        let mut b = city_board(5);
+        let output = play(&mut b, vec![PLACE, Input::Resign { player_id: 1 }, UNDO]);
+        assert!(matches!(output, Output::AskPlaceTile { player_id: 0, .. }));
+        assert!(b.players[1].resigned);
+        let output = play(&mut b, vec![Input::Redo { player_id: 0 }]);
+        assert!(matches!(output, Output::AskPlaceToken { player_id: 0, .. }));
+        assert!(b.players[1].resigned);
+        assert_eq!(b.active_players(), 1);
+    }
+
+    #[test]
+    fn only_the_host_may_undo() {
+        let mut b = city_board(5);
+        b.rules.undo_host = 1;
+        let output = play(&mut b, vec![PLACE, UNDO]);
+        assert!(matches!(output, Output::Error { err: Error::UndoNotAllowed { player_id: 0 } }));
+        let output = play(&mut b, vec![Input::Undo { player_id: 1, scope: UndoScope::Action }]);
+        assert!(matches!(output, Output::AskPlaceTile { player_id: 0, .. }));
+    }
+
+    #[test]
+    fn road_pays_a_point_per_tile() {
+        let mut b = board();
+        // the road leaves (0, 0) twice and comes back around the square
+        b.place(tile(0, "FRRF", vec![road(&[Dir4::Right]), road(&[Dir4::Down])]), Pos::new(0, 0), Spin::No);
+        b.place(tile(1, "FFRR", vec![road(&[Dir4::Left, Dir4::Down])]), Pos::new(1, 0), Spin::No);
+        b.place(tile(2, "RFFR", vec![road(&[Dir4::Up, Dir4::Left])]), Pos::new(1, 1), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert_eq!(b.object_value(id), Some(3));
+        deploy(&mut b, 1, TokenTarget::Segment { pos: Pos::new(1, 0), id: 0 });
+
+        b.place(tile(3, "RRFF", vec![road(&[Dir4::Up, Dir4::Right])]), Pos::new(0, 1), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert_eq!(b.object_id(Pos::new(0, 0), 1), Some(id));
+        assert!(b.object(id).unwrap().complete());
+        assert_eq!(b.object(id).unwrap().members.len(), 5);
+        let event = b.score_object(id);
+        assert_eq!(event.points, 4);
+        assert_eq!(event.player_ids, vec![1]);
+        assert_eq!(event.category, ScoreCategory::Road);
+        assert_eq!(event.object_id, Some(id));
+        assert_eq!(b.players[1].history[0].object_id, Some(id));
+        assert_eq!(b.players[1].score, 4);
+        assert_eq!(b.players[0].score, 0);
+        assert_eq!(b.players[1].tokens[&Token::Meeple], 7);
+        assert_eq!(b.object(id).unwrap().token_count, 0);
+    }
+
+    #[test]
+    fn city_pays_double_when_complete() {
+        let pennant = || segment(SegmentType::CitySegment { pennant: 1, goods: Vec::new() }, halves(&[Dir4::Right]));
+        let middle = || tile(1, "FCFC", vec![city(&[Dir4::Left, Dir4::Right])]);
+
+        // open at game end, one per tile and pennant
+        let mut b = board();
+        b.place(tile(0, "FCFF", vec![pennant()]), Pos::new(0, 0), Spin::No);
+        deploy(&mut b, 0, TokenTarget::Segment { pos: Pos::new(0, 0), id: 0 });
+        b.place(middle(), Pos::new(1, 0), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert!(!b.object(id).unwrap().complete());
+        let event = b.score_object(id);
+        assert_eq!(event.points, 2 + 1);
+        assert_eq!(event.player_ids, vec![0]);
+
+        // closed, two per tile and pennant, to both tied players
+        let mut b = board();
+        b.place(tile(0, "FCFF", vec![pennant()]), Pos::new(0, 0), Spin::No);
+        b.place(tile(2, "FFFC", vec![city(&[Dir4::Left])]), Pos::new(2, 0), Spin::No);
+        deploy(&mut b, 0, TokenTarget::Segment { pos: Pos::new(0, 0), id: 0 });
+        deploy(&mut b, 1, TokenTarget::Segment { pos: Pos::new(2, 0), id: 0 });
+        b.place(middle(), Pos::new(1, 0), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        assert!(b.object(id).unwrap().complete());
+        let event = b.score_object(id);
+        assert_eq!(event.points, 2 * (3 + 1));
+        assert_eq!(event.player_ids, vec![0, 1]);
+        assert_eq!(event.category, ScoreCategory::City);
+        assert_eq!((b.players[0].score, b.players[1].score), (8, 8));
+        assert_eq!((b.players[0].tokens[&Token::Meeple], b.players[1].tokens[&Token::Meeple]), (7, 7));
+    }
+
+    #[test]
+    fn monastery_pays_nine_when_surrounded() {
+        let mut b = board();
+        let mut monastery = tile(0, "FFFF", Vec::new());
+        monastery.features.push(Feature { typ: FeatureType::Monastry });
+        b.place(monastery, Pos::new(0, 0), Spin::No);
+        assert_eq!(b.features, vec![(Pos::new(0, 0), 0)]);
+        deploy(&mut b, 1, TokenTarget::Feature { pos: Pos::new(0, 0), id: 0 });
+        let around = Pos::new(0, 0).around();
+        for (i, &pos) in around[..7].iter().enumerate() {
+            let feature = b.tiles[&Pos::new(0, 0)].feature(0).unwrap();
+            assert!(!feature.complete(&b));
+            assert_eq!(feature.value(&b), 1 + i as u32);
+            b.place(tile(1 + i as u8, "FFFF", Vec::new()), pos, Spin::No);
+        }
+
+        // an open monastery pays its tile and each neighbour at game end
+        let snapshot = b.snapshot();
+        let event = b.score_feature(Pos::new(0, 0), 0);
+        assert_eq!((event.points, event.player_ids), (8, vec![1]));
+        b.restore(snapshot);
+
+        b.place(tile(8, "FFFF", Vec::new()), around[7], Spin::No);
+        assert!(b.tiles[&Pos::new(0, 0)].feature(0).unwrap().complete(&b));
+        let event = b.score_feature(Pos::new(0, 0), 0);
+        assert_eq!(event.points, 9);
+        assert_eq!(event.player_ids, vec![1]);
+        assert_eq!(event.category, ScoreCategory::Monastery);
+        assert_eq!(b.players[1].score, 9);
+        assert_eq!(b.players[1].tokens[&Token::Meeple], 7);
+        assert!(!b.tiles[&Pos::new(0, 0)].feature(0).unwrap().occupied(&b));
+    }
+
+    /// Cities 0 (up) and 3 (down) with field 1 (left) next to both and
+    /// field 2 (right) next to city 0. Player 0 farms field 1 and player 1
+    /// farms field 2 twice.
+    fn farm_board(farm: FarmRule) -> Board {
+        let mut b = board();
+        b.rules.farm = farm;
+        b.rules.join_occupied = true;
+        b.place(tile(0, "CFCF", vec![
+            city(&[Dir4::Up]),
+            field(halves(&[Dir4::Left]), vec![0, 3]),
+            field(halves(&[Dir4::Right]), vec![0]),
+            city(&[Dir4::Down]),
+        ]), Pos::new(0, 0), Spin::No);
+        b.place(tile(1, "FFCF", vec![city(&[Dir4::Down])]), Pos::new(0, -1), Spin::No);
+        deploy(&mut b, 0, TokenTarget::Segment { pos: Pos::new(0, 0), id: 1 });
+        deploy(&mut b, 1, TokenTarget::Segment { pos: Pos::new(0, 0), id: 2 });
+        deploy(&mut b, 1, TokenTarget::Segment { pos: Pos::new(0, 0), id: 2 });
+        b
+    }
+
+    #[test]
+    fn farms_pay_three_per_city_to_each_field() {
+        // city 3 is still open and pays nobody
+        let mut b = farm_board(FarmRule::CurrentEdition);
+        let events = b.score_farms();
+        assert_eq!(events.iter().map(|e| (e.points, e.player_ids.clone())).collect::<Vec<_>>(), vec![(3, vec![0]), (3, vec![1])]);
+
+        let mut b = farm_board(FarmRule::CurrentEdition);
+        b.place(tile(2, "CFFF", vec![city(&[Dir4::Up])]), Pos::new(0, 1), Spin::No);
+        let events = b.score_farms();
+        assert!(events.iter().all(|e| e.category == ScoreCategory::Farm));
+        let field = b.object_id(Pos::new(0, 0), 1);
+        assert_eq!(events[0].object_id, field);
+        assert_eq!((b.players[0].score, b.players[1].score), (6, 3));
+        assert_eq!((b.players[0].tokens[&Token::Meeple], b.players[1].tokens[&Token::Meeple]), (7, 7));
+    }
+
+    #[test]
+    fn first_edition_farms_pay_four_per_city_to_the_majority() {
+        let mut b = farm_board(FarmRule::FirstEdition);
+        b.score_farms();
+        assert_eq!((b.players[0].score, b.players[1].score), (0, 4));
+
+        // city 3 goes to field 1 alone, city 0 to the two farmers of field 2
+        let mut b = farm_board(FarmRule::FirstEdition);
+        b.place(tile(2, "CFFF", vec![city(&[Dir4::Up])]), Pos::new(0, 1), Spin::No);
+        let events = b.score_farms();
+        assert_eq!(events.iter().map(|e| (e.points, e.player_ids.clone())).collect::<Vec<_>>(), vec![(4, vec![1]), (4, vec![0])]);
+        assert_eq!((b.players[0].score, b.players[1].score), (4, 4));
+        assert_eq!((b.players[0].tokens[&Token::Meeple], b.players[1].tokens[&Token::Meeple]), (7, 7));
+    }
+
+    #[test]
+    fn placement_is_checked_against_the_board() {
+        let mut b = board();
+        b.place(tile(0, "FCFF", Vec::new()), Pos::new(0, 0), Spin::No);
+        let plain = tile(1, "CFFF", Vec::new());
+        assert!(matches!(b.check_place(&plain, Pos::new(0, 0), Spin::CounterClockwise), Err(PlaceError::Occupied)));
+        assert!(matches!(b.check_place(&plain, Pos::new(2, 0), Spin::CounterClockwise), Err(PlaceError::NoNeighbour)));
+        assert!(matches!(b.check_place(&plain, Pos::new(1, 0), Spin::No),
+            Err(PlaceError::SideMismatch { dir: Dir4::Left, side: SideType::Field, other: SideType::City })));
+        assert!(matches!(b.check_place(&plain, Pos::new(-1, 0), Spin::Clockwise),
+            Err(PlaceError::SideMismatch { dir: Dir4::Right, side: SideType::City, other: SideType::Field })));
+        // turned so that its city faces left
+        assert!(b.check_place(&plain, Pos::new(1, 0), Spin::CounterClockwise).is_ok());
+        assert!(matches!(b.check_place(&tile(2, "SFFF", Vec::new()), Pos::new(-1, 0), Spin::No), Err(PlaceError::RiverNotConnected)));
+    }
+
+    #[test]
+    fn river_may_bend_but_not_turn_back() {
+        let mut b = board();
+        // the river flows down from the source, then turns to the right
+        b.place(tile(0, "FFSF", Vec::new()), Pos::new(0, 0), Spin::No);
+        b.place(tile(1, "SSFF", Vec::new()), Pos::new(0, 1), Spin::No);
+        let s_bend = tile(2, "FFSS", Vec::new());
+        let u_turn = tile(3, "SFFS", Vec::new());
+        assert!(b.check_place(&s_bend, Pos::new(1, 1), Spin::No).is_ok());
+        assert!(matches!(b.check_place(&u_turn, Pos::new(1, 1), Spin::No), Err(PlaceError::RiverUTurn)));
+        // the same pieces, turned into each other
+        assert!(matches!(b.check_place(&s_bend, Pos::new(1, 1), Spin::Clockwise), Err(PlaceError::RiverUTurn)));
+        assert!(b.check_place(&u_turn, Pos::new(1, 1), Spin::CounterClockwise).is_ok());
+        // straight on is always fine
+        assert!(b.check_place(&tile(4, "FSFS", Vec::new()), Pos::new(1, 1), Spin::No).is_ok());
+    }
+
+    #[test]
+    fn placements_list_every_fitting_spot() {
+        let mut b = board();
+        b.place(tile(0, "FCFF", Vec::new()), Pos::new(0, 0), Spin::No);
+        b.place(tile(1, "FFFC", Vec::new()), Pos::new(1, 0), Spin::No);
+        let frontier = [(0, -1), (0, 1), (-1, 0), (1, -1), (1, 1), (2, 0)].map(|(x, y)| Pos::new(x, y));
+        assert_eq!(b.frontier().into_iter().collect::<HashSet<_>>(), HashSet::from(frontier));
+
+        // the city may face any way but onto the fields of the placed tiles
+        let city = tile(2, "CFFF", Vec::new());
+        let facing = |spin: Spin| Dir4::Up.rotate(spin);
+        let mut expected: Vec<(Pos, usize)> = frontier.iter()
+            .flat_map(|&pos| (0..4).map(move |i| (pos, i)))
+            .filter(|&(pos, i)| !b.have_tile(pos + facing(Spin::from_id(i))))
+            .collect();
+        expected.sort();
+        assert_eq!(expected.len(), 18);
+        let mut placements: Vec<(Pos, usize)> = b.placements(&city).into_iter().map(|(pos, spin)| (pos, spin.id())).collect();
+        placements.sort();
+        assert_eq!(placements, expected);
+        assert!(b.can_place_anywhere(&city));
+        assert!(!b.can_place_anywhere(&tile(3, "CCCC", Vec::new())));
+    }
+
+    #[test]
+    fn final_score_pays_open_objects_then_ends() {
+        let mut b = board();
+        b.place(tile(0, "FRFF", vec![road(&[Dir4::Right])]), Pos::new(0, 0), Spin::No);
+        b.place(tile(1, "FRFR", vec![road(&[Dir4::Left, Dir4::Right])]), Pos::new(1, 0), Spin::No);
+        deploy(&mut b, 0, TokenTarget::Segment { pos: Pos::new(0, 0), id: 0 });
+        let pennant = segment(SegmentType::CitySegment { pennant: 1, goods: Vec::new() }, halves(&[Dir4::Right]));
+        b.place(tile(2, "FCFF", vec![pennant]), Pos::new(0, 5), Spin::No);
+        b.place(tile(3, "FCFC", vec![city(&[Dir4::Left, Dir4::Right])]), Pos::new(1, 5), Spin::No);
+        deploy(&mut b, 1, TokenTarget::Segment { pos: Pos::new(1, 5), id: 0 });
+        let mut monastery = tile(4, "FFFF", Vec::new());
+        monastery.features.push(Feature { typ: FeatureType::Monastry });
+        b.place(monastery, Pos::new(0, 10), Spin::No);
+        b.place(tile(5, "FFFF", Vec::new()), Pos::new(1, 10), Spin::No);
+        deploy(&mut b, 1, TokenTarget::Feature { pos: Pos::new(0, 10), id: 0 });
+        b.phase = Phase::FinalScore;
+
+        let mut game = b.game();
+        let GeneratorState::Yielded(Output::Scored { events }) = game.resume_with(Input::Nothing) else { panic!("nothing scored") };
+        let paid: Vec<(ScoreCategory, u32, Vec<usize>)> = events.into_iter().map(|e| (e.category, e.points, e.player_ids)).collect();
+        assert_eq!(paid, vec![
+            (ScoreCategory::Road, 2, vec![0]),
+            (ScoreCategory::City, 2 + 1, vec![1]),
+            (ScoreCategory::Monastery, 2, vec![1]),
+        ]);
+        let GeneratorState::Yielded(Output::GameEnd { standings }) = game.resume_with(Input::Nothing) else { panic!("the game did not end") };
+        assert!(matches!(game.resume_with(Input::Nothing), GeneratorState::Complete(())));
+        drop(game);
+        assert_eq!(b.phase, Phase::End);
+        let standings: Vec<(usize, u32, usize)> = standings.iter().map(|s| (s.player_id, s.score, s.rank)).collect();
+        assert_eq!(standings, vec![(1, 5, 1), (0, 2, 2)]);
+        assert_eq!((b.players[0].tokens[&Token::Meeple], b.players[1].tokens[&Token::Meeple]), (7, 7));
+    }
+
+    #[test]
+    fn goods_pay_ten_to_each_tied_leader() {
+        let mut b = Board::create(3, ExtensionState { enabled: HashSet::new() });
+        b.players[0].goods.insert(Goods::Wine, 2);
+        b.players[1].goods.insert(Goods::Wine, 2);
+        b.players[1].goods.insert(Goods::Cloth, 1);
+        b.players[2].goods.insert(Goods::Wine, 1);
+        b.players[2].goods.insert(Goods::Cloth, 1);
+        let paid: Vec<(u32, Vec<usize>)> = b.score_goods().into_iter().map(|e| (e.points, e.player_ids)).collect();
+        assert_eq!(paid, vec![(10, vec![0, 1]), (10, vec![1, 2])]);
+        assert_eq!(b.players.iter().map(|p| p.score).collect::<Vec<_>>(), vec![10, 20, 10]);
+    }
+
+    #[test]
+    fn standings_break_ties_on_goods_and_put_resigned_last() {
+        let mut b = Board::create(5, ExtensionState { enabled: HashSet::new() });
+        for (player, score) in b.players.iter_mut().zip([10, 12, 10, 20, 10]) {
+            player.score = score;
+        }
+        b.players[0].goods.insert(Goods::Grain, 1);
+        b.players[4].goods.insert(Goods::Wine, 1);
+        b.players[3].resigned = true;
+        let standings: Vec<(usize, usize)> = b.standings().iter().map(|s| (s.player_id, s.rank)).collect();
+        assert_eq!(standings, vec![(1, 1), (0, 2), (4, 2), (2, 4), (3, 5)]);
+    }
+
+    #[test]
+    fn builders_and_pigs_go_back_with_their_meeple() {
+        let mut b = Board::create(2, ExtensionState { enabled: HashSet::from([Extension::Ex2Builder, Extension::Ex2Pig]) });
+        for player in &mut b.players {
+            player.init_tokens(&b.extension);
+        }
+        b.place(tile(0, "FCFF", vec![city(&[Dir4::Right]), field(halves(&[Dir4::Up, Dir4::Down, Dir4::Left]), vec![0])]), Pos::new(0, 0), Spin::No);
+        let town = TokenTarget::Segment { pos: Pos::new(0, 0), id: 0 };
+        let farm = TokenTarget::Segment { pos: Pos::new(0, 0), id: 1 };
+        deploy(&mut b, 0, town);
+        b.deploy_belonging(town, BelongingToken::Builder).unwrap();
+        deploy(&mut b, 1, farm);
+        b.deploy_belonging(farm, BelongingToken::Pig).unwrap();
+        assert!(!b.players[0].have_belonging(BelongingToken::Builder));
+        assert!(!b.players[1].have_belonging(BelongingToken::Pig));
+
+        b.place(tile(1, "FFFC", vec![city(&[Dir4::Left])]), Pos::new(1, 0), Spin::No);
+        let id = b.object_id(Pos::new(0, 0), 0).unwrap();
+        b.score_object(id);
+        assert_eq!(b.players[0].tokens[&Token::Meeple], 7);
+        assert_eq!(b.players[0].belonging_tokens[&BelongingToken::Builder], 1);
+
+        b.score_farms();
+        assert_eq!(b.players[1].tokens[&Token::Meeple], 7);
+        assert_eq!(b.players[1].belonging_tokens[&BelongingToken::Pig], 1);
+        assert!(!b.segment(Pos::new(0, 0), 1).unwrap().occupied());
+    }
+}
@@ -13,6 +13,7 @@ pub struct Feature {
     pub typ: FeatureType,
 }
 
+#[derive(Clone)]
 pub struct PlacedFeature {
     pub typ: FeatureType,
     pub tokens: Vec<PlacedToken>,
@@ -27,6 +28,14 @@ impl PlacedFeature {
             pos: pos
         }
     }
+    /// A monastery counts itself and each tile around it, 9 when surrounded.
+    pub fn value(&self, board: &Board) -> u32 {
+        match self.typ {
+            FeatureType::Monastry => {
+                1 + self.pos.around().iter().filter(|x| board.have_tile(**x)).count() as u32
+            }
+        }
+    }
 }
 
 impl CanScore for PlacedFeature {
@@ -1,13 +1,200 @@
+use std::fmt;
+use crate::core::lib::*;
+use crate::core::object::ObjectId;
+use crate::core::player::ScoreCategory;
+use crate::core::segment::SegmentType;
+use crate::core::tilepic::SegmentPicType;
+use crate::core::tiledata::SerialNumber;
+use crate::core::token::{BelongingToken, Goods, Token};
 
+/// A place on a placed tile that can hold a token: the `id`-th segment or
+/// the `id`-th feature of the tile at `pos`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TokenTarget {
+    Segment { pos: Pos, id: usize },
+    Feature { pos: Pos, id: usize },
+}
+
+impl TokenTarget {
+    pub fn pos(&self) -> Pos {
+        match self {
+            TokenTarget::Segment { pos, .. } | TokenTarget::Feature { pos, .. } => *pos,
+        }
+    }
+}
+
+/// What `Board::game` is resumed with.
 pub enum Input {
     Nothing,
+    PlaceTile { pos: Pos, spin: Spin },
+    PlaceToken { target: TokenTarget, token: Token },
+    /// Attach a builder or pig to the object of `target`, which must already
+    /// hold one of the current player's tokens.
+    PlaceBelonging { target: TokenTarget, token: BelongingToken },
+    SkipToken,
+    /// May be sent by any player at any prompt.
+    Resign { player_id: usize },
+    /// Takes back the last action, or everything since the start of the
+    /// last turn, subject to `Rules::undo_limit` and `Rules::undo_host_only`.
+    Undo { player_id: usize, scope: UndoScope },
+    /// Replays what the last `Undo` took back.
+    Redo { player_id: usize },
 }
 
+/// How far an `Input::Undo` goes back.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UndoScope {
+    /// The last tile placement or token deployment.
+    Action,
+    /// The whole turn of the last action, back to before its tile was drawn.
+    Turn,
+}
+
+/// Points paid to `player_ids` for the object containing `object`, if any.
+#[derive(Clone)]
+pub struct ScoreEvent {
+    pub player_ids: Vec<usize>,
+    pub points: u32,
+    pub category: ScoreCategory,
+    pub object: Option<TokenTarget>,
+    /// The scored road, city or field, or the city paid for under
+    /// `FarmRule::FirstEdition`. `None` for features and goods.
+    pub object_id: Option<ObjectId>,
+}
+
+/// A player's place at the end of the game. Players with the same `rank`
+/// are tied.
+#[derive(Clone, Debug)]
+pub struct Standing {
+    pub player_id: usize,
+    pub score: u32,
+    pub rank: usize,
+}
+
+/// What `Board::game` yields. The `Ask*` variants list every legal answer,
+/// so a client can build menus and validate moves without knowing the rules.
+/// The other variants are notifications and are resumed with `Input::Nothing`.
+/// When the engine answers an input with `Output::Error`, the next input is
+/// taken as another answer to the same question.
+#[derive(Clone)]
 pub enum Output {
     Nothing,
     Error { err: Error },
+    AskPlaceTile {
+        player_id: usize,
+        serial: SerialNumber,
+        choices: Vec<(Pos, Spin)>,
+    },
+    AskPlaceToken {
+        player_id: usize,
+        pos: Pos,
+        tokens: Vec<Token>,
+        targets: Vec<TokenTarget>,
+        belongings: Vec<(TokenTarget, BelongingToken)>,
+    },
+    Scored { events: Vec<ScoreEvent> },
+    /// The current player completed a city and took its goods.
+    GoodsCollected { player_id: usize, goods: Vec<Goods> },
+    /// Sent once after the final scoring, best first.
+    GameEnd { standings: Vec<Standing> },
+    /// The drawn tile fits nowhere and was removed from the game.
+    TileDiscarded { serial: SerialNumber },
+    /// The drawn tile fits nowhere and was shuffled back into the stack.
+    TileReshuffled { serial: SerialNumber },
+}
+
+/// Why `Board::check_place` rejected a placement. Directions are on the board.
+#[derive(Clone, Debug)]
+pub enum PlaceError {
+    Occupied,
+    NoNeighbour,
+    /// The tile's `dir` edge is `side`, but the neighbour there has `other`.
+    SideMismatch { dir: Dir4, side: SideType, other: SideType },
+    /// A river tile must continue the river through one of its river edges.
+    RiverNotConnected,
+    /// Two consecutive river turns in the same direction.
+    RiverUTurn,
+}
+
+impl fmt::Display for PlaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn edge(dir: &Dir4) -> &'static str {
+            match dir {
+                Dir4::Up => "top",
+                Dir4::Right => "right",
+                Dir4::Down => "bottom",
+                Dir4::Left => "left",
+            }
+        }
+        fn side(side: &SideType) -> &'static str {
+            match side {
+                SideType::City => "a city",
+                SideType::Road => "a road",
+                SideType::Field => "a field",
+                SideType::River => "a river",
+            }
+        }
+        match self {
+            PlaceError::Occupied => write!(f, "there is already a tile there"),
+            PlaceError::NoNeighbour => write!(f, "the tile must touch a placed tile"),
+            PlaceError::SideMismatch { dir, side: s, other } =>
+                write!(f, "the {} edge is {} but the neighbour has {}", edge(dir), side(s), side(other)),
+            PlaceError::RiverNotConnected => write!(f, "the river must continue the existing river"),
+            PlaceError::RiverUTurn => write!(f, "the river cannot turn the same way twice in a row"),
+        }
+    }
 }
 
+#[derive(Clone, Debug)]
 pub enum Error {
     Nothing,
-}
\ No newline at end of file
+    /// The input does not answer the question that was asked.
+    InvalidInput,
+    OutOfToken { token: Token },
+    OutOfBelonging { token: BelongingToken },
+    IllegalPlacement { pos: Pos, spin: Spin, reason: PlaceError },
+    IllegalTokenTarget { target: TokenTarget },
+    ObjectOccupied { target: TokenTarget },
+    TypeMismatch { expected: SegmentType, found: SegmentType },
+    HintMismatch,
+    InvalidSegmentPicType { typ: SegmentPicType },
+    InvalidSegmentPic { picname: String, tile_id: u8, typ: SegmentPicType },
+    UnknownPack { packname: (u8, char) },
+    /// `picname` and `tile_id` name the picture and tile being parsed, if any.
+    Parse { line: usize, column: usize, picname: Option<String>, tile_id: Option<u8>, message: String },
+    MissingAsset { path: String, reason: String },
+    UndoNotAllowed { player_id: usize },
+    NothingToUndo,
+    NothingToRedo,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Nothing => write!(f, "unknown error"),
+            Error::InvalidInput => write!(f, "this action is not expected now"),
+            Error::OutOfToken { token } => write!(f, "no {token:?} left"),
+            Error::OutOfBelonging { token } => write!(f, "no {token:?} left"),
+            Error::IllegalPlacement { pos, spin, reason } => write!(f, "the tile cannot be placed at ({}, {}) with {spin:?}: {reason}", pos.x, pos.y),
+            Error::IllegalTokenTarget { target } => write!(f, "a token cannot be placed on {target:?}"),
+            Error::ObjectOccupied { target } => write!(f, "{target:?} belongs to an object that already has a token"),
+            Error::TypeMismatch { expected, found } => write!(f, "expected a segment of type {expected:?}, found {found:?}"),
+            Error::HintMismatch => write!(f, "segments with different hint kinds cannot be merged"),
+            Error::InvalidSegmentPicType { typ } => write!(f, "{typ:?} is not a segment type"),
+            Error::InvalidSegmentPic { picname, tile_id, typ } => write!(f, "tile {tile_id} of picture {picname}: invalid shape for a {typ:?} segment"),
+            Error::UnknownPack { packname } => write!(f, "unknown pack {}{}", packname.0, packname.1),
+            Error::Parse { line, column, picname, tile_id, message } => {
+                write!(f, "tiledata.txt:{line}:{column}: {message}")?;
+                match (picname, tile_id) {
+                    (Some(picname), Some(tile_id)) => write!(f, " (picture {picname}, tile {tile_id})"),
+                    (Some(picname), None) => write!(f, " (picture {picname})"),
+                    _ => Ok(())
+                }
+            }
+            Error::MissingAsset { path, reason } => write!(f, "cannot load {path}: {reason}"),
+            Error::UndoNotAllowed { player_id } => write!(f, "player {player_id} may not undo or redo"),
+            Error::NothingToUndo => write!(f, "there is nothing to undo"),
+            Error::NothingToRedo => write!(f, "there is nothing to redo"),
+        }
+    }
+}
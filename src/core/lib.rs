@@ -104,7 +104,7 @@ impl ops::Neg<> for Dir8 {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Spin {
     No,
     Clockwise,
@@ -412,6 +412,33 @@ impl ExtensionState {
     }
 }
 
+/// SplitMix64, so that a seed gives the same sequence on every platform.
+#[derive(Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    pub fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
 pub fn dis_cir(pos: Pos, radius: f32, n: u8) -> Vec<Pos> {
     let mut ret = Vec::new();
     let mut ang = (n % 2) as f32 * std::f32::consts::PI / 2f32;
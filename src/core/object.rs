@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use crate::core::board::Board;
+use crate::core::tile::PlacedTile;
 use crate::core::segment::{PlacedSegment, SegmentType};
 use crate::core::lib::*;
-use crate::core::token::PlacedToken;
+use crate::core::token::{Goods, PlacedToken};
 
 pub trait CanScore {
     fn occupied(&self, board: &Board) -> bool {
@@ -13,48 +14,219 @@ pub trait CanScore {
     }
     fn complete(&self, board: &Board) -> bool;
     fn iterate_token(&self, board: &Board) -> impl Iterator<Item=&PlacedToken>;
+    fn winners(&self, board: &Board) -> Vec<usize> {
+        majority(self.iterate_token(board))
+    }
+}
+
+/// Players whose tokens have the greatest total `Token::strength`, sorted;
+/// all of them on a tie. Tokens without strength never win on their own.
+pub fn majority<'a>(tokens: impl Iterator<Item=&'a PlacedToken>) -> Vec<usize> {
+    let mut count: HashMap<usize, u32> = HashMap::new();
+    for token in tokens {
+        *count.entry(token.player_id).or_insert(0) += token.token.strength();
+    }
+    let max = count.values().copied().max().unwrap_or(0);
+    if max == 0 { return Vec::new() }
+    let mut ret: Vec<usize> = count.into_iter().filter(|&(_, c)| c == max).map(|(id, _)| id).collect();
+    ret.sort();
+    ret
 }
 
+/// A view of an object of the registry with its segments borrowed from the
+/// board.
 pub struct Object<'a> {
     pub segments: Vec<&'a PlacedSegment>,
-    pub opened_side: HashSet<(Pos, Dir8)>
+    pub data: &'a ObjectData,
 }
 
 impl<'a> Object<'a> {
-    pub fn create(seg: &'a PlacedSegment) -> Object<'a> {
-        Object {
-            segments: vec![seg],
-            opened_side: HashSet::new()
+    pub fn typ(&self) -> &'a SegmentType {
+        &self.data.typ
+    }
+    pub fn pennants(&self) -> u32 {
+        self.segments.iter()
+            .map(|seg| match seg.typ {
+                SegmentType::CitySegment { pennant, .. } => pennant as u32,
+                _ => 0
+            })
+            .sum()
+    }
+    pub fn goods(&self) -> Vec<Goods> {
+        self.segments.iter()
+            .flat_map(|seg| match &seg.typ {
+                SegmentType::CitySegment { goods, .. } => goods.clone(),
+                _ => Vec::new()
+            })
+            .collect()
+    }
+    /// Points the object is worth now: its full value if complete, the
+    /// reduced end-of-game value otherwise.
+    pub fn value(&self) -> u32 {
+        let tiles = self.data.tiles.len() as u32;
+        match self.typ() {
+            SegmentType::RoadSegment { .. } => tiles,
+            SegmentType::CitySegment { .. } => {
+                if self.data.complete() { 2 * (tiles + self.pennants()) }
+                else { tiles + self.pennants() }
+            }
+            _ => 0
         }
     }
-    pub fn push(&mut self, seg: &'a PlacedSegment) -> Result<(), String> {
-        match self.segments.first() {
-            Some(&first_seg) => {
-                if first_seg.typ.is_same_type(&seg.typ) {
-                    self.segments.push(seg);
-                    Ok(())
-                }
-                else {
-                    Err("Not the same type pushing into object".to_string())
+}
+
+/// A segment of a placed tile: the tile's position and the segment index.
+pub type SegKey = (Pos, usize);
+
+/// Names an object for as long as the game lasts. Every segment gets its
+/// own id when placed; once objects merge, all their ids resolve to the
+/// same surviving object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(pub usize);
+
+/// What the registry keeps for each object, stored at its union-find root.
+#[derive(Clone)]
+pub struct ObjectData {
+    pub typ: SegmentType,
+    pub members: Vec<SegKey>,
+    pub tiles: HashSet<Pos>,
+    pub opened_side: HashSet<(Pos, Dir8)>,
+    pub token_count: usize,
+}
+
+impl ObjectData {
+    pub fn complete(&self) -> bool {
+        !self.typ.is_field() && self.opened_side.is_empty()
+    }
+}
+
+/// Every object on the board, merged incrementally as tiles are placed
+/// instead of flood-filling the board on each query.
+#[derive(Clone, Default)]
+pub struct ObjectRegistry {
+    ids: HashMap<SegKey, ObjectId>,
+    parent: Vec<ObjectId>,
+    data: HashMap<ObjectId, ObjectData>,
+}
+
+impl ObjectRegistry {
+    /// The surviving id of the object `id` was merged into.
+    pub fn find(&self, mut id: ObjectId) -> ObjectId {
+        while let Some(&parent) = self.parent.get(id.0) {
+            if parent == id { break; }
+            id = parent;
+        }
+        id
+    }
+    /// The id of the object containing the segment `key`.
+    pub fn id_of(&self, key: SegKey) -> Option<ObjectId> {
+        self.ids.get(&key).map(|&id| self.find(id))
+    }
+    pub fn get(&self, id: ObjectId) -> Option<&ObjectData> {
+        self.data.get(&self.find(id))
+    }
+    fn get_mut(&mut self, id: ObjectId) -> Option<&mut ObjectData> {
+        let root = self.find(id);
+        self.data.get_mut(&root)
+    }
+    pub fn roots(&self) -> impl Iterator<Item=(&ObjectId, &ObjectData)> {
+        self.data.iter()
+    }
+    /// Merges the smaller object into the larger one.
+    fn union(&mut self, a: ObjectId, b: ObjectId) {
+        let (mut ra, mut rb) = (self.find(a), self.find(b));
+        if ra == rb { return }
+        if self.data[&ra].members.len() < self.data[&rb].members.len() {
+            (ra, rb) = (rb, ra);
+        }
+        let small = self.data.remove(&rb).unwrap();
+        self.parent[rb.0] = ra;
+        let big = self.data.get_mut(&ra).unwrap();
+        big.members.extend(small.members);
+        big.tiles.extend(small.tiles);
+        big.opened_side.extend(small.opened_side);
+        big.token_count += small.token_count;
+    }
+    /// Registers the segments of the tile just placed at `pos` and joins
+    /// them to the objects of its neighbours.
+    pub fn add_tile(&mut self, tiles: &HashMap<Pos, PlacedTile>, pos: Pos) {
+        let tile = &tiles[&pos];
+        for seg in tile.segs() {
+            let id = ObjectId(self.parent.len());
+            self.parent.push(id);
+            self.ids.insert((pos, seg.id), id);
+            self.data.insert(id, ObjectData {
+                typ: seg.typ.clone(),
+                members: vec![(pos, seg.id)],
+                tiles: HashSet::from([pos]),
+                opened_side: seg.direction.iter().map(|&dir| (pos, dir)).collect(),
+                token_count: seg.tokens.len(),
+            });
+        }
+        for seg in tile.segs() {
+            let id = self.ids[&(pos, seg.id)];
+            for &dir in &seg.direction {
+                let next_pos = pos + dir.dir;
+                let Some(next_tile) = tiles.get(&next_pos) else { continue };
+                if let Some(other) = next_tile.find_seg(-dir, &seg.typ) {
+                    let other_id = self.ids[&(next_pos, other.id)];
+                    self.union(id, other_id);
+                    self.get_mut(other_id).unwrap().opened_side.remove(&(next_pos, -dir));
                 }
-            }
-            None => {
-                self.segments.push(seg);
-                Ok(())
+                self.get_mut(id).unwrap().opened_side.remove(&(pos, dir));
             }
         }
     }
-    pub fn typ(&self) -> &'a SegmentType {
-        &self.segments[0].typ
+    pub fn add_tokens(&mut self, id: ObjectId, n: usize) {
+        if let Some(data) = self.get_mut(id) { data.token_count += n; }
+    }
+    pub fn remove_tokens(&mut self, id: ObjectId, n: usize) {
+        if let Some(data) = self.get_mut(id) { data.token_count -= n; }
     }
 }
 
 impl<'a> CanScore for Object<'a> {
     fn complete(&self, board: &Board) -> bool {
-        if self.typ().is_field() { return false }
-        self.opened_side.len() == 0
+        self.data.complete()
     }
     fn iterate_token(&self, board: &Board) -> impl Iterator<Item=&PlacedToken> {
         self.segments.iter().flat_map(|seg| seg.tokens.iter())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::token::Token;
+
+    fn token(token: Token, player_id: usize) -> PlacedToken {
+        PlacedToken { token, belonging: Vec::new(), public_belonging: Vec::new(), player_id }
+    }
+
+    #[test]
+    fn majority_of_nothing_is_empty() {
+        assert!(majority(std::iter::empty()).is_empty());
+    }
+
+    #[test]
+    fn majority_counts_meeples() {
+        let tokens = [token(Token::Meeple, 1), token(Token::Meeple, 0), token(Token::Meeple, 1)];
+        assert_eq!(majority(tokens.iter()), vec![1]);
+    }
+
+    #[test]
+    fn majority_returns_all_tied_players() {
+        let tokens = [token(Token::Meeple, 2), token(Token::Meeple, 0), token(Token::Meeple, 1), token(Token::Meeple, 0), token(Token::Meeple, 2)];
+        assert_eq!(majority(tokens.iter()), vec![0, 2]);
+    }
+
+    #[test]
+    fn big_meeple_counts_as_two() {
+        let tokens = [token(Token::BigMeeple, 0), token(Token::Meeple, 1)];
+        assert_eq!(majority(tokens.iter()), vec![0]);
+        let tokens = [token(Token::BigMeeple, 0), token(Token::Meeple, 1), token(Token::Meeple, 1)];
+        assert_eq!(majority(tokens.iter()), vec![0, 1]);
+        let tokens = [token(Token::BigMeeple, 0), token(Token::Meeple, 1), token(Token::Meeple, 1), token(Token::Meeple, 1)];
+        assert_eq!(majority(tokens.iter()), vec![1]);
+    }
+}
@@ -1,10 +1,40 @@
 use std::collections::HashMap;
-use crate::core::token::{BelongingToken, PlacedBelongingToken, PlacedToken, Token};
+use crate::core::io::{Error, TokenTarget};
+use crate::core::lib::{Extension, ExtensionState};
+use crate::core::object::ObjectId;
+use crate::core::token::{Goods, BelongingToken, PlacedBelongingToken, PlacedToken, Token};
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ScoreCategory {
+    Road,
+    City,
+    Monastery,
+    Farm,
+    Goods,
+    /// Expansion bonuses that belong to no object.
+    Bonus,
+}
+
+/// One payment to a player: what was scored, for how much, and when.
+#[derive(Clone, Debug)]
+pub struct ScoreRecord {
+    pub category: ScoreCategory,
+    pub points: u32,
+    pub turn: u32,
+    pub object: Option<TokenTarget>,
+    pub object_id: Option<ObjectId>,
+}
+
+#[derive(Clone)]
 pub struct Player {
     pub id: usize,
     pub tokens: HashMap<Token, u32>,
     pub belonging_tokens: HashMap<BelongingToken, u32>,
+    pub resigned: bool,
+    pub score: u32,
+    pub goods: HashMap<Goods, u32>,
+    pub breakdown: HashMap<ScoreCategory, u32>,
+    pub history: Vec<ScoreRecord>,
 }
 
 impl Player {
@@ -12,9 +42,31 @@ impl Player {
         Player {
             id: id,
             tokens: HashMap::new(),
-            belonging_tokens: HashMap::new()
+            belonging_tokens: HashMap::new(),
+            resigned: false,
+            score: 0,
+            goods: HashMap::new(),
+            breakdown: HashMap::new(),
+            history: Vec::new()
         }
     }
+    pub fn init_tokens(&mut self, extension: &ExtensionState) {
+        self.tokens.insert(Token::Meeple, 7);
+        if extension.is_enabled(Extension::Ex1LargeMeeple) {
+            self.tokens.insert(Token::BigMeeple, 1);
+        }
+        if extension.is_enabled(Extension::Ex2Builder) {
+            self.belonging_tokens.insert(BelongingToken::Builder, 1);
+        }
+        if extension.is_enabled(Extension::Ex2Pig) {
+            self.belonging_tokens.insert(BelongingToken::Pig, 1);
+        }
+    }
+    pub fn add_score(&mut self, record: ScoreRecord) {
+        self.score += record.points;
+        *self.breakdown.entry(record.category).or_insert(0) += record.points;
+        self.history.push(record);
+    }
     pub fn have_token(&self, token: Token) -> bool {
         if let Some(i) = self.tokens.get(&token) {
             *i != 0
@@ -23,7 +75,7 @@ impl Player {
             false
         }
     }
-    pub fn place_token(&mut self, token: Token) -> Result<PlacedToken, String> {
+    pub fn place_token(&mut self, token: Token) -> Result<PlacedToken, Error> {
         if let Some(i) = self.tokens.get_mut(&token) {
             if *i > 0 {
                 *i -= 1;
@@ -35,7 +87,13 @@ impl Player {
                 })
             }
         }
-        Err("Token not found".to_string())
+        Err(Error::OutOfToken { token })
+    }
+    pub fn return_token(&mut self, token: Token) {
+        *self.tokens.entry(token).or_insert(0) += 1;
+    }
+    pub fn return_belonging(&mut self, belonging_token: BelongingToken) {
+        *self.belonging_tokens.entry(belonging_token).or_insert(0) += 1;
     }
     pub fn have_belonging(&self, belonging_token: BelongingToken) -> bool {
         if let Some(i) = self.belonging_tokens.get(&belonging_token) {
@@ -45,7 +103,7 @@ impl Player {
             false
         }
     }
-    pub fn place_belonging(&mut self, belonging_token: BelongingToken) -> Result<PlacedBelongingToken, String> {
+    pub fn place_belonging(&mut self, belonging_token: BelongingToken) -> Result<PlacedBelongingToken, Error> {
         if let Some(i) = self.belonging_tokens.get_mut(&belonging_token) {
             if *i > 0 {
                 *i -= 1;
@@ -55,6 +113,6 @@ impl Player {
                 })
             }
         }
-        Err("Belonging Token not found".to_string())
+        Err(Error::OutOfBelonging { token: belonging_token })
     }
 }
\ No newline at end of file
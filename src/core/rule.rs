@@ -0,0 +1,47 @@
+/// What happens to a drawn tile that cannot be placed anywhere.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnplaceableRule {
+    /// Remove the tile from the game and draw another one.
+    Discard,
+    /// Shuffle the tile back into the stack and draw another one.
+    Reshuffle,
+}
+
+/// How farmers are scored at the end of the game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FarmRule {
+    /// Each completed city pays 4 points once, to the players with the most
+    /// farmers over all fields adjacent to it.
+    FirstEdition,
+    /// Each field pays 3 points per adjacent completed city to its own
+    /// farmer majority.
+    CurrentEdition,
+}
+
+/// Rule variants chosen by the host before the game starts.
+pub struct Rules {
+    pub unplaceable: UnplaceableRule,
+    pub farm: FarmRule,
+    /// Lets a token join an object that already holds tokens, for the
+    /// expansion variants and magic placements that allow it.
+    pub join_occupied: bool,
+    /// How many actions can be taken back; 0 disables undo.
+    pub undo_limit: usize,
+    /// Only `undo_host` may undo or redo. Otherwise any player may.
+    pub undo_host_only: bool,
+    /// The player who hosts the game.
+    pub undo_host: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            unplaceable: UnplaceableRule::Discard,
+            farm: FarmRule::CurrentEdition,
+            join_occupied: false,
+            undo_limit: 0,
+            undo_host_only: true,
+            undo_host: 0,
+        }
+    }
+}
@@ -1,23 +1,24 @@
+use crate::core::io::Error;
 use crate::core::lib::*;
 use crate::core::tilepic::{Hint, SegmentPicType};
-use crate::core::token::PlacedToken;
+use crate::core::token::{Goods, PlacedToken};
 
-#[derive(Clone, Hash, PartialEq, Eq)]
+#[derive(Clone, Hash, PartialEq, Eq, Debug)]
 pub enum SegmentType {
-    CitySegment { pennant: u8 },
+    CitySegment { pennant: u8, goods: Vec<Goods> },
     RoadSegment { adj_road_city: Vec<usize> },
     FieldSegment { adj_city: Vec<usize> },
     RiverSegment,
 }
 
 impl SegmentType {
-    pub fn new_from_segment_pic_type(typ: SegmentPicType) -> Result<Self, String> {
+    pub fn new_from_segment_pic_type(typ: SegmentPicType) -> Result<Self, Error> {
         match typ {
-            SegmentPicType::City => Ok(SegmentType::CitySegment { pennant: 0 }),
+            SegmentPicType::City => Ok(SegmentType::CitySegment { pennant: 0, goods: Vec::new() }),
             SegmentPicType::Road => Ok(SegmentType::RoadSegment { adj_road_city: Vec::new() }),
             SegmentPicType::Field => Ok(SegmentType::FieldSegment { adj_city: Vec::new() }),
             SegmentPicType::River => Ok(SegmentType::RiverSegment {}),
-            _ => Err(format!("Can't new SegmentType from {typ:?}"))
+            _ => Err(Error::InvalidSegmentPicType { typ })
         }
     }
     pub fn is_same_type(&self, other: &SegmentType) -> bool {
@@ -31,8 +32,8 @@ impl SegmentType {
     }
     pub fn add_adj(&mut self, other: &mut Self, self_id: usize, other_id: usize) {
         match (self, other) {
-            (SegmentType::CitySegment {pennant: _}, SegmentType::FieldSegment {adj_city: a}) => a.push(self_id),
-            (SegmentType::FieldSegment {adj_city: a}, SegmentType::CitySegment {pennant: _}) => a.push(other_id),
+            (SegmentType::CitySegment { .. }, SegmentType::FieldSegment {adj_city: a}) => a.push(self_id),
+            (SegmentType::FieldSegment {adj_city: a}, SegmentType::CitySegment { .. }) => a.push(other_id),
             _ => ()
         }
     }
@@ -83,8 +84,10 @@ pub struct Segment {
     pub hint: Hint,
 }
 
+#[derive(Clone)]
 pub struct PlacedSegment {
     pub pos: Pos,
+    pub id: usize,
     pub typ: SegmentType,
     pub direction: Vec<Dir8>,
     pub tokens: Vec<PlacedToken>,
@@ -92,9 +95,10 @@ pub struct PlacedSegment {
 }
 
 impl PlacedSegment {
-    pub fn create(pos: Pos, seg: Segment, orient: Spin) -> PlacedSegment {
+    pub fn create(pos: Pos, id: usize, seg: Segment, orient: Spin) -> PlacedSegment {
         PlacedSegment {
             pos: pos,
+            id: id,
             typ: seg.typ,
             direction: seg.direction.iter()
                 .map(|x| x.rotate(orient))
@@ -2,9 +2,10 @@ use std::rc::Rc;
 use photon_rs::PhotonImage;
 use crate::core::lib::*;
 use crate::core::segment::{Segment, PlacedSegment, SegmentType};
-use crate::core::feature::Feature;
+use crate::core::feature::{Feature, PlacedFeature};
 use crate::core::tiledata::SerialNumber;
 
+#[derive(Clone)]
 pub struct Tile {
     pub start: bool,
     pub serial: SerialNumber,
@@ -16,16 +17,28 @@ pub struct Tile {
 }
 
 impl Tile {
+    /// Number of sides the river flows through; a source or a lake has one.
+    pub fn river_ends(&self) -> usize {
+        self.sides.iter().filter(|&&side| side == SideType::River).count()
+    }
+    pub fn is_river(&self) -> bool {
+        self.river_ends() > 0
+    }
+    pub fn side(&self, self_spin: Spin, direction: Dir4) -> SideType {
+        self.sides[direction.rotate(-self_spin).id()]
+    }
     pub fn can_connect(&self, self_spin: Spin, other: &PlacedTile, direction: Dir4) -> bool {
-        let side = self.sides[direction.rotate(-self_spin).id()];
-        let other_side = other.sides[(-direction).id()];
+        let side = self.side(self_spin, direction);
+        let other_side = other.side(-direction);
         side == other_side
     }
 }
 
+#[derive(Clone)]
 pub struct PlacedTile {
     pos: Pos,
     segs: Vec<PlacedSegment>,
+    features: Vec<PlacedFeature>,
     sides: [SideType; 4],
     orient: Spin,
 }
@@ -34,11 +47,33 @@ impl PlacedTile {
     pub fn create(pos: Pos, tile: Tile, orient: Spin) -> PlacedTile {
         PlacedTile {
             pos: pos,
-            segs: tile.segments.into_iter().map(|x| PlacedSegment::create(pos, x, orient)).collect(),
-            sides: tile.sides,
+            segs: tile.segments.into_iter().enumerate().map(|(i, x)| PlacedSegment::create(pos, i, x, orient)).collect(),
+            features: tile.features.into_iter().map(|x| PlacedFeature::create(x, pos)).collect(),
+            sides: std::array::from_fn(|i| tile.sides[Dir4::from_id(i).rotate(-orient).id()]),
             orient,
         }
     }
+    pub fn side(&self, direction: Dir4) -> SideType {
+        self.sides[direction.id()]
+    }
+    pub fn seg(&self, id: usize) -> Option<&PlacedSegment> {
+        self.segs.get(id)
+    }
+    pub fn seg_mut(&mut self, id: usize) -> Option<&mut PlacedSegment> {
+        self.segs.get_mut(id)
+    }
+    pub fn segs(&self) -> &Vec<PlacedSegment> {
+        &self.segs
+    }
+    pub fn features(&self) -> &Vec<PlacedFeature> {
+        &self.features
+    }
+    pub fn feature(&self, id: usize) -> Option<&PlacedFeature> {
+        self.features.get(id)
+    }
+    pub fn feature_mut(&mut self, id: usize) -> Option<&mut PlacedFeature> {
+        self.features.get_mut(id)
+    }
     pub fn find_seg(&self, dir: Dir8, typ: &SegmentType) -> Option<&PlacedSegment> {
         for seg in &self.segs {
             if seg.typ.is_same_type(typ) {
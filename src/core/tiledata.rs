@@ -1,15 +1,17 @@
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use photon_rs::PhotonImage;
-use photon_rs::native::{open_image};
+use crate::core::asset::AssetRoot;
 use photon_rs::transform::crop;
-use crate::core::feature::Feature;
+use crate::core::feature::{Feature, FeatureType};
+use crate::core::io::Error;
 use crate::core::segment::{Segment, SegmentType};
 use crate::core::tilepic::*;
 use crate::core::lib::*;
 use crate::core::tile::Tile;
+use crate::core::token::Goods;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SerialNumber {
     pub packid: u8,
     pub picname: String,
@@ -27,10 +29,29 @@ pub fn find_segment<T>(segments: &Vec<T>, predicate: impl Fn(&T) -> bool) -> Vec
     map
 }
 
+/// The halves of `sides` still in `free`, for a part of an `else` area. On
+/// a side a line enters, only the halves next to another of `sides` are
+/// taken, so that the areas on either side of the line stay apart.
+fn else_sides(free: &HashSet<Dir8>, sides: &[Dir4], line_sides: &HashSet<Dir4>) -> Vec<Dir8> {
+    let mut ret = Vec::new();
+    for &dir in sides {
+        let cw = sides.contains(&dir.rotate(Spin::Clockwise));
+        let ccw = sides.contains(&dir.rotate(Spin::CounterClockwise));
+        for clockwise_side in [true, false] {
+            let next = if clockwise_side { cw } else { ccw };
+            let half = Dir8::new(dir, clockwise_side);
+            if free.contains(&half) && (next || !line_sides.contains(&dir) || !(cw || ccw)) {
+                ret.push(half);
+            }
+        }
+    }
+    ret
+}
+
 impl Segment {
-    pub fn eat(&mut self, other: &mut Segment) -> Result<(), String> {
+    pub fn eat(&mut self, other: &mut Segment) -> Result<(), Error> {
         if !self.typ.is_same_type(&other.typ) {
-            return Err("Not same type segment eaten".to_string());
+            return Err(Error::TypeMismatch { expected: self.typ.clone(), found: other.typ.clone() });
         }
         match (&mut self.hint, &mut other.hint) {
             (Hint::LineSegment { line: line1 }, Hint::LineSegment { line: line2 }) => {
@@ -39,33 +60,33 @@ impl Segment {
             (Hint::Hintline { pos }, Hint::Hintline { pos: pos2 }) => {
                 pos.append(pos2);
             }
-            _ => return Err("Not same hintline of type segment eaten".to_string())
+            _ => return Err(Error::HintMismatch)
         }
         self.direction.append(&mut other.direction);
         Ok(())
     }
 }
 
-pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
-    let pics = parse()?;
-    let ret = Vec::new();
+pub fn read_tile_data(assets: &AssetRoot, pack: HashSet<Extension>) -> Result<Vec<Tile>, Error> {
+    let pics = parse(assets)?;
+    let mut ret = Vec::new();
     for pic in pics {
-        let img = match open_image(format!("/Users/shedarshian/Desktop/bot/chiharu/chiharu2/plugins/games/cacason/carcassonne_asset/{}.png", &pic.name)) {
-            Ok(img) => img,
-            Err(_) => return Err(format!("Pic {} cannot found", &pic.name))
-        };
+        let img = assets.open_picture(&pic.name)?;
         for tilet in pic.tiles {
             let mut segments = Vec::new();
             let mut helper = HashMap::new();
             let mut all_sides: HashSet<Dir8> = Dir8::ALL.iter().cloned().collect();
+            let mut line_sides: HashSet<Dir4> = HashSet::new();
             let elsed: bool = false;
             for seg in tilet.segments {
                 match (&seg.typ, &seg.pic) {
                     (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::OneSide { dir, width })  => {
                         let my_sides = vec![Dir8::new(*dir, true), Dir8::new(*dir, false)];
-                        my_sides.iter().map(|x| all_sides.remove(x));
+                        for x in &my_sides {
+                            all_sides.remove(x);
+                        }
                         segments.push(Segment {
-                            typ: SegmentType::new_from_segment_pic_type(seg.typ).unwrap(),
+                            typ: SegmentType::new_from_segment_pic_type(seg.typ)?,
                             direction: my_sides,
                             hint: if seg.hint.is_empty() {
                                 Hint::Hintline {
@@ -75,9 +96,11 @@ pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
                     }
                     (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::DoubleSide { dir, width }) => {
                         let my_sides = vec![Dir8::new(dir.0, true), Dir8::new(dir.0, false), Dir8::new(dir.1, true), Dir8::new(dir.1, false)];
-                        my_sides.iter().map(|x| all_sides.remove(x));
+                        for x in &my_sides {
+                            all_sides.remove(x);
+                        }
                         segments.push(Segment {
-                            typ: SegmentType::new_from_segment_pic_type(seg.typ).unwrap(),
+                            typ: SegmentType::new_from_segment_pic_type(seg.typ)?,
                             direction: my_sides,
                             hint: if seg.hint.is_empty() {
                                 Hint::Hintline {
@@ -104,8 +127,9 @@ pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
                             }
                         };
                         let pos2 = (find_pos(&pos.0), find_pos(&pos.1));
+                        line_sides.extend(my_sides.iter().map(|x| x.dir));
                         segments.push(Segment {
-                            typ: SegmentType::new_from_segment_pic_type(seg.typ).unwrap(),
+                            typ: SegmentType::new_from_segment_pic_type(seg.typ)?,
                             direction: my_sides,
                             hint: if seg.hint.is_empty() {
                                 Hint::LineSegment {
@@ -115,14 +139,18 @@ pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
                     }
                     (SegmentPicType::Road, SegmentPicData::OneSide { dir, width }) => {
                         let my_sides = vec![Dir8::new(*dir, true), Dir8::new(*dir, false)];
-                        my_sides.iter().map(|x| all_sides.remove(x));
-                        my_sides.iter().map(|x| segments.push(Segment {
-                            typ: SegmentType::FieldSegment { adj_city: vec![] },
-                            direction: vec![*x],
-                            hint: Hint::Hintline {
-                                pos: vec![x.tileside_hintline(*width / 2)]
-                            }
-                        }));
+                        for x in &my_sides {
+                            all_sides.remove(x);
+                        }
+                        for x in &my_sides {
+                            segments.push(Segment {
+                                typ: SegmentType::FieldSegment { adj_city: vec![] },
+                                direction: vec![*x],
+                                hint: Hint::Hintline {
+                                    pos: vec![x.tileside_hintline(*width / 2)]
+                                }
+                            });
+                        }
                         segments.push(Segment {
                             typ: SegmentType::RoadSegment { adj_road_city: vec![] },
                             direction: my_sides,
@@ -137,34 +165,68 @@ pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
                         let (mut i0, mut i1) = (roads[road.0], roads[road.1]);
                         if i0 > i1 { (i0, i1) = (i1, i0); }
                         let mut r1 = segments.remove(i1);
-                        segments[i0].eat(&mut r1);
+                        segments[i0].eat(&mut r1)?;
                     }
                     (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::Else { road_sides, adj_city }) => {
-                        if road_sides.len() == 0 {
+                        let invalid = || Error::InvalidSegmentPic { picname: pic.name.clone(), tile_id: tilet.id, typ: seg.typ };
+                        // each of `road_sides` is an area of its own; together
+                        // they must take every half side left
+                        let mut parts = Vec::new();
+                        if road_sides.is_empty() {
+                            parts.push(all_sides.iter().cloned().collect());
+                        }
+                        else {
+                            let roads = find_segment(&segments, |x: &Segment| x.typ.is_road()).len();
+                            for part in road_sides {
+                                let sides = match part {
+                                    AllRoadSide::Road { id, .. } if *id as usize >= roads => return Err(invalid()),
+                                    AllRoadSide::Road { sides, .. } | AllRoadSide::Manual { sides } => sides,
+                                };
+                                let my_sides = else_sides(&all_sides, sides, &line_sides);
+                                if my_sides.is_empty() { return Err(invalid()); }
+                                for x in &my_sides {
+                                    all_sides.remove(x);
+                                }
+                                parts.push(my_sides);
+                            }
+                            if !all_sides.is_empty() { return Err(invalid()); }
+                        }
+                        let cities = find_segment(&segments, |x: &Segment| x.typ.is_city());
+                        let single = parts.len() == 1;
+                        for direction in parts {
                             segments.push(Segment {
-                                typ: SegmentType::new_from_segment_pic_type(seg.typ).unwrap(),
-                                direction: all_sides.iter().cloned().collect(),
-                                hint: seg.hint
+                                typ: SegmentType::new_from_segment_pic_type(seg.typ)?,
+                                hint: if single { seg.hint.clone() } else {
+                                    Hint::Hintline {
+                                        pos: direction.iter().map(|x| x.tileside_hintline(0)).collect()
+                                    }},
+                                direction
                             });
                             let l = segments.len() - 1;
                             let (s1, s2) = segments.split_at_mut(l);
-                            for (i, s) in s1.iter_mut().enumerate() {
-                                if s.typ.is_area() {
-                                    s.typ.add_adj(&mut s2[0].typ, i, l);
+                            // the cities a field touches, as given, or else all of them
+                            if seg.typ == SegmentPicType::Field && !adj_city.is_empty() {
+                                for &city in adj_city {
+                                    let &i = cities.get(city as usize).ok_or_else(invalid)?;
+                                    s1[i].typ.add_adj(&mut s2[0].typ, i, l);
+                                }
+                            }
+                            else {
+                                for (i, s) in s1.iter_mut().enumerate() {
+                                    if s.typ.is_area() {
+                                        s.typ.add_adj(&mut s2[0].typ, i, l);
+                                    }
                                 }
                             }
-                        }
-                        else {
-
                         }
                     }
-                    _ => return Err(format!("Segment {:?} type and pic not valid", &seg))
+                    _ => return Err(Error::InvalidSegmentPic { picname: pic.name.clone(), tile_id: tilet.id, typ: seg.typ })
                 }
             }
             for (sub_id, num) in tilet.nums.iter().enumerate() {
                 let ext: Extension = match num.packname.try_into() {
                     Ok(p) => p,
-                    Err(_) => return Err(format!("pack {}{} not found", num.packname.0, num.packname.1))
+                    Err(_) => return Err(Error::UnknownPack { packname: num.packname })
                 };
                 if !pack.contains(&ext) { continue; }
                 let mut tile = Tile {
@@ -180,9 +242,76 @@ pub fn read_tile_data(pack: HashSet<Extension>) -> Result<Vec<Tile>, String> {
                     segments: segments.clone(),
                     features: vec![],
                 };
-                // order
+                for extra in &num.extra_order {
+                    match extra {
+                        ExtraOrderData::Feature { typ: SegmentPicType::City, id, feature, .. } => {
+                            let cities = find_segment(&tile.segments, |x: &Segment| x.typ.is_city());
+                            if let Some(SegmentType::CitySegment { pennant, goods }) = cities.get(*id as usize).map(|&i| &mut tile.segments[i].typ) {
+                                if feature == "pennant" { *pennant += 1; }
+                                else if let Some(good) = Goods::from_name(feature) { goods.push(good); }
+                            }
+                        }
+                        ExtraOrderData::Start {} => tile.start = true,
+                        ExtraOrderData::Addable { name, .. } if name == "Cloister" => {
+                            tile.features.push(Feature { typ: FeatureType::Monastry });
+                        }
+                        _ => ()
+                    }
+                }
+                ret.extend(std::iter::repeat_n(tile, num.num as usize));
             }
         }
     }
     Ok(ret)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn else_parts_split_at_lines() {
+        // a road from left to right: the field above and the field below
+        let free: HashSet<Dir8> = Dir8::ALL.into_iter().collect();
+        let lines = HashSet::from([Dir4::Left, Dir4::Right]);
+        let above = else_sides(&free, &[Dir4::Left, Dir4::Up, Dir4::Right], &lines);
+        assert_eq!(above, vec![Dir8::new(Dir4::Left, true), Dir8::new(Dir4::Up, true), Dir8::new(Dir4::Up, false), Dir8::new(Dir4::Right, false)]);
+        let below = else_sides(&free, &[Dir4::Right, Dir4::Down, Dir4::Left], &lines);
+        assert_eq!(below, vec![Dir8::new(Dir4::Right, true), Dir8::new(Dir4::Down, true), Dir8::new(Dir4::Down, false), Dir8::new(Dir4::Left, false)]);
+
+        // a side no line enters is taken whole, when still free
+        let free: HashSet<Dir8> = free.into_iter().filter(|x| *x != Dir8::new(Dir4::Up, true)).collect();
+        assert_eq!(else_sides(&free, &[Dir4::Up], &lines), vec![Dir8::new(Dir4::Up, false)]);
+    }
+
+    #[test]
+    fn deck_follows_counts_packs_and_extras() {
+        let dir = std::env::temp_dir().join(format!("cacason-{}-deck", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tiledata.txt"), "Pictureabcd
+0 CFFF City u 12 Field else *1 1a start *2 2d
+1 CRFR City u 12 Road l-r 0 Field else (R0-l-u-r,r-d-l) *3 1a City 0 pennant; City 0 Wine; Cloister 32,32 *1 0b
+").unwrap();
+        photon_rs::native::save_image(PhotonImage::new(vec![255; 128 * 128 * 4], 128, 128), dir.join("abcd.png")).unwrap();
+
+        let deck = read_tile_data(&AssetRoot::new(&dir), HashSet::from([Extension::Ex1Tiles, Extension::Ex2Goods])).unwrap();
+        let serials: Vec<(u8, u8, u8)> = deck.iter().map(|tile| (tile.serial.id, tile.serial.sub_id, tile.serial.packid)).collect();
+        // the river pack of tile 1 is not enabled
+        assert_eq!(serials, vec![(0, 0, 1), (0, 1, 2), (0, 1, 2), (1, 0, 1), (1, 0, 1), (1, 0, 1)]);
+        assert_eq!(deck.iter().map(|tile| tile.start).collect::<Vec<_>>(), vec![true, false, false, false, false, false]);
+        assert_eq!(deck[0].img.get_width(), 64);
+
+        let tile = &deck[3];
+        assert!(matches!(&tile.segments[0].typ, SegmentType::CitySegment { pennant: 1, goods } if goods == &vec![Goods::Wine]));
+        assert_eq!(tile.features.len(), 1);
+        assert!(matches!(tile.features[0].typ, FeatureType::Monastry));
+        // the field is split by the road, each half next to the city
+        let fields: Vec<&Segment> = tile.segments.iter().filter(|seg| seg.typ.is_field()).collect();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].direction, vec![Dir8::new(Dir4::Left, true), Dir8::new(Dir4::Right, false)]);
+        assert_eq!(fields[1].direction.len(), 4);
+        assert!(fields.iter().all(|seg| matches!(&seg.typ, SegmentType::FieldSegment { adj_city } if adj_city == &vec![0])));
+        assert!(deck[0].segments.iter().all(|seg| !matches!(seg.typ, SegmentType::CitySegment { pennant: 1, .. })));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
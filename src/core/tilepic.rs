@@ -1,7 +1,9 @@
-use nom::{IResult, Parser, branch::alt, bytes::complete::take, character::{char, complete::{multispace1, i32, u8, alpha1}}, combinator::{map, value, opt, success}, multi::{separated_list1, many1, count}, sequence::{delimited, separated_pair, preceded}};
+use nom::{IResult, Parser, branch::alt, bytes::complete::take, character::{char, complete::{digit1, multispace1, i32, u8, alpha1}}, combinator::{cut, map, not, value, opt, success}, multi::{separated_list1, many1, count}, sequence::{delimited, separated_pair, preceded}};
 use nom::bytes::complete::tag;
-use nom::error::Error;
+use nom::error::{context, ContextError, ErrorKind, ParseError};
 use trpl::Either;
+use crate::core::asset::AssetRoot;
+use crate::core::io::Error as ProgramError;
 use crate::core::lib::*;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Copy)]
@@ -85,7 +87,11 @@ impl Hint {
                 ret
             }
             Hint::LineSegment { line } => {
-
+                let len = line.len();
+                (0..n as usize).map(|i| {
+                    let count = n as usize / len + (if i % len < n as usize % len { 1 } else { 0 });
+                    Hint::along(line[i % len], i / len + 1, count + 1)
+                }).collect()
             }
         }
     }
@@ -96,7 +102,7 @@ impl Hint {
                 if len > n as usize { return pos[n as usize].0; }
                 let repeat = n as usize / len;
                 let p1 = n as usize % len;
-                let (p, line) = pos[p1];
+                let (p, line) = pos[p1].clone();
                 if line != HintLine::None {
                     if (line == HintLine::UD) == (repeat == 1) { p + Pos{x: 0, y: Hint::RADIUS} } // TODO maybe not right
                     else { p + Pos{y: 0, x: Hint::RADIUS} }
@@ -105,10 +111,15 @@ impl Hint {
                 else { p }
             }
             Hint::LineSegment { line } => {
-
+                let len = line.len();
+                Hint::along(line[n as usize % len], n as usize / len + 1, n as usize / len + 2)
             }
         }
     }
+    fn along((p1, p2): (Pos, Pos), num: usize, den: usize) -> Pos {
+        let (num, den) = (num as i32, den as i32);
+        Pos { x: p1.x + (p2.x - p1.x) * num / den, y: p1.y + (p2.y - p1.y) * num / den }
+    }
 }
 impl Default for Hint {
     fn default() -> Self {
@@ -140,7 +151,7 @@ pub enum AnyPos {
 
 pub enum ExtraOrderData {
     Start {},
-    Addable { param: Option<Either<i32, Dir4>>, pos: Option<AnyPos> },
+    Addable { name: String, param: Option<Either<i32, Dir4>>, pos: Option<AnyPos> },
     Feature { typ: SegmentPicType, id: u8, feature: String, param: Option<Either<i32, Dir4>>},
     Hint { typ: SegmentPicType, id: u8, hint: Hint },
     RoadWidth { typ: SegmentPicType, id: u8, width: i32 }
@@ -167,8 +178,92 @@ pub struct PicData {
     pub tiles: Vec<TilePicData>,
 }
 
-fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
-    let pos = || map(separated_pair(i32::<&str, Error::<&str>>, char(','), i32), |p| {
+/// A parse failure in `tiledata.txt`. Of the alternatives tried, keeps the
+/// one that got furthest, with what it expected there and the constructs
+/// around it, innermost first.
+#[derive(Debug)]
+pub struct DslError<'a> {
+    pub input: &'a str,
+    pub expected: Vec<String>,
+    pub context: Vec<(&'a str, &'static str)>,
+}
+
+impl<'a> DslError<'a> {
+    fn new(input: &'a str, expected: String) -> Self {
+        DslError { input, expected: vec![expected], context: Vec::new() }
+    }
+    /// The name of the picture being parsed, read back from where its
+    /// `picture` context started.
+    fn picname(&self) -> Option<String> {
+        self.context.iter().find(|(_, ctx)| *ctx == "picture")
+            .and_then(|(input, _)| input.strip_prefix("Picture"))
+            .map(|rest| rest.chars().take(4).collect())
+    }
+    fn tile_id(&self) -> Option<u8> {
+        self.context.iter().find(|(_, ctx)| *ctx == "tile")
+            .and_then(|(input, _)| input.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|id| id.parse().ok())
+    }
+    /// "expected ... in segment of tile", leaving out the picture and the
+    /// tile, which are named on their own.
+    fn message(&self) -> String {
+        let mut message = format!("expected {}", self.expected.join(" or "));
+        let context: Vec<&str> = self.context.iter().map(|(_, ctx)| *ctx)
+            .filter(|ctx| *ctx != "picture" && *ctx != "tile").collect();
+        if !context.is_empty() {
+            message += &format!(" in {}", context.join(" of "));
+        }
+        message
+    }
+}
+
+impl<'a> ParseError<&'a str> for DslError<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        let expected = match kind {
+            ErrorKind::Tag => "keyword".to_string(),
+            ErrorKind::Digit => "number".to_string(),
+            ErrorKind::MultiSpace => "whitespace".to_string(),
+            ErrorKind::Eof => "more input".to_string(),
+            kind => format!("{kind:?}"),
+        };
+        DslError::new(input, expected)
+    }
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+    fn from_char(input: &'a str, c: char) -> Self {
+        DslError::new(input, format!("{c:?}"))
+    }
+    fn or(mut self, other: Self) -> Self {
+        if other.input.len() < self.input.len() { return other }
+        if other.input.len() == self.input.len() {
+            for expected in other.expected {
+                if !self.expected.contains(&expected) { self.expected.push(expected); }
+            }
+        }
+        self
+    }
+}
+
+impl<'a> ContextError<&'a str> for DslError<'a> {
+    fn add_context(input: &'a str, ctx: &'static str, mut other: Self) -> Self {
+        other.context.push((input, ctx));
+        other
+    }
+}
+
+/// Turns an error raised after `parser` consumed some input into a failure,
+/// so that the list around it reports the error instead of quietly ending
+/// before the item.
+fn commit<'a, O>(mut parser: impl Parser<&'a str, Output=O, Error=DslError<'a>>) -> impl Parser<&'a str, Output=O, Error=DslError<'a>> {
+    move |input: &'a str| match parser.parse(input) {
+        Err(nom::Err::Error(e)) if e.input.len() < input.len() => Err(nom::Err::Failure(e)),
+        result => result,
+    }
+}
+
+fn parser(s: &str) -> IResult<&str, Vec<PicData>, DslError<'_>> {
+    let pos = || map(separated_pair(i32::<&str, DslError<'_>>, char(','), i32), |p| {
         Pos::new(p.0, p.1)
     });
     let dir4 = || alt((
@@ -180,7 +275,7 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
     let city = || value(SegmentPicType::City, tag("City"));
     let field = || value(SegmentPicType::Field, tag("Field"));
     let road = || value(SegmentPicType::Road, tag("Road"));
-    let cut = value(SegmentPicType::Cut, tag("Cut"));
+    let cut_type = value(SegmentPicType::Cut, tag("Cut"));
     let area = || alt((city(), field()));
     let line = || alt((
         road(),
@@ -198,15 +293,15 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
         map((point(), u8), |(p, n)| {AnyPos::Point { typ: p, index: n.into() }}),
         map(dir4(), |d| {AnyPos::Dir { dir: d }})
     ));
-    let hint = || delimited(char('['), separated_list1(char('/'), alt((
+    let hint = || context("hint", delimited(char('['), separated_list1(char('/'), alt((
         map(pos(), |x| (x, HintLine::None)),
         (pos(), value(HintLine::LR, tag("/lr"))),
         (pos(), value(HintLine::UD, tag("/ud"))),
-    ))), char(']'));
+    ))), char(']')));
     fn unwrap<T: Default>(x: Option<T>) -> T {
         x.unwrap_or_default()
     }
-    let op_hint = || map(opt(preceded(sep, hint())), unwrap);
+    let op_hint = || map(opt(preceded(sep, commit(hint()))), unwrap);
     let point_segment = map((point(), sep, pos(), op_hint()), |(t, _, p, l)| {
         SegmentPic {
             typ: t, hint: Hint::Hintline { pos: l },
@@ -219,7 +314,7 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
             pic: SegmentPicData::Line { pos: (p1, p2), depth: d },
         }
     });
-    let cut_segment = map((cut, sep, any_pos(), char('-'), any_pos()), |(t, _, p1, _, p2)| {
+    let cut_segment = map((cut_type, sep, any_pos(), char('-'), any_pos()), |(t, _, p1, _, p2)| {
         SegmentPic {
             typ: t, hint: Hint::default(),
             pic: SegmentPicData::Line { pos: (p1, p2), depth: 0 },
@@ -258,7 +353,7 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
         }
     });
     let segment = alt((point_segment, line_segment, cut_segment, tunnel_segment, oneside_segment, doubleside_segment, else_segment));
-    let segments = separated_list1(sep, segment);
+    let segments = separated_list1(sep, context("segment", commit(segment)));
     let op_sep_params = || opt(preceded(sep, delimited(char('('), alt((
         map(i32, |i| Either::Left(i)),
         map(dir4(), |i| Either::Right(i)),
@@ -266,13 +361,13 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
     let start_extra = map(tag("start"), |_| ExtraOrderData::Start{});
     let tile_addable = alt(["Portal", "Volcano", "Dragon", "Gold", "Gingerbread", "Festival", "Hill", "Vineyard", "MageWitch", "Rake", "Club", "Shield"]
         .map(tag));
-    let tile_addable_extra = map((tile_addable, op_sep_params()), |(_, p)| {
-        ExtraOrderData::Addable { param: p, pos: None }
+    let tile_addable_extra = map((tile_addable, op_sep_params()), |(n, p)| {
+        ExtraOrderData::Addable { name: n.to_string(), param: p, pos: None }
     });
     let tile_addable_pos = alt(["Garden", "Tower", "Cloister", "Shrine", "Flier", "Circus", "Acrobat"]
         .map(tag));
-    let tile_addable_pos_extra = map((tile_addable_pos, op_sep_params(), sep, any_pos()), |(_, p, _, pos)| {
-        ExtraOrderData::Addable { param: p, pos: Some(pos) }
+    let tile_addable_pos_extra = map((tile_addable_pos, op_sep_params(), sep, any_pos()), |(n, p, _, pos)| {
+        ExtraOrderData::Addable { name: n.to_string(), param: p, pos: Some(pos) }
     });
     let addable = alt([
         "Cathedral", "Inn", "pennant", "well", "Cloth", "Wine", "Grain", "Princess", "Pigherd",
@@ -295,8 +390,13 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
         }
     });
     let extra = alt((start_extra, tile_addable_extra, tile_addable_pos_extra, addable_extra, hint_extra, roadwidth_extra));
-    let extras = opt(preceded(sep, separated_list1((char(';'), sep), extra)));
-    let packname = (u8, take(1u8));
+    // what follows the tile count, unless it is another count, the next tile
+    // or picture, or the end, is an extra order, and so is what follows a
+    // `;`. A misspelt keyword fails before consuming anything, where `commit`
+    // would let the list end quietly, so an extra is `cut` instead
+    let next_item = alt((tag("*"), tag("Picture"), tag("."), digit1));
+    let extras = opt(preceded((sep, not(next_item)), separated_list1((char(';'), sep), context("extra order", cut(extra)))));
+    let packname = context("pack name", (u8, take(1u8)));
     let num = map((char('*'), u8, sep, packname, extras), |(_, num, _, s, e)| {
         NumData {
             num: num, packname: (s.0, s.1.chars().nth(0).expect("")), extra_order: match e {
@@ -304,32 +404,38 @@ fn parser(s: &str) -> IResult<&str, Vec<PicData>> {
             }
         }
     });
-    let nums = separated_list1(sep, num);
-    let sides = count(alt((
+    let nums = separated_list1(sep, context("tile count", commit(num)));
+    let sides = context("tile sides", count(alt((
         value(SideType::City, char('C')),
         value(SideType::Road, char('R')),
         value(SideType::Field, char('F')),
         value(SideType::River, char('S')),
-    )), 4);
+    )), 4));
     let tile = map((u8, sep, sides, sep, segments, sep, nums), |(i, _, s, _, seg, _, n)| {
         TilePicData {
             id: i, sides: s.try_into().expect(""), segments: seg, nums: n
         }
     });
-    let tiles = separated_list1(sep, tile);
+    let tiles = separated_list1(sep, context("tile", commit(tile)));
     let pic = map((tag("Picture"), take(4u32), sep, tiles), |(_, n, _, t)| {
         PicData { name: n.to_string(), tiles: t }
     });
-    let mut pics = separated_list1(sep, pic);
+    let mut pics = separated_list1(sep, context("picture", commit(pic)));
     pics.parse(s)
 }
 
-pub fn parse() -> Result<Vec<PicData>, String> {
-    let path = "/Users/shedarshian/Desktop/bot/chiharu/chiharu2/plugins/games/cacason/carcassonne_asset/tiledata.txt";
-    let mut content = match std::fs::read_to_string(path) {
-        Ok(content) => content,
-        Err(x) => return Err(format!("Failed to read tiledata.txt: {}", x)),
-    };
+fn parse_error(content: &str, remaining: &str, picname: Option<String>, tile_id: Option<u8>, message: String) -> ProgramError {
+    let offset = content.len() - remaining.len();
+    let line = content[..offset].matches('\n').count() + 1;
+    let column = offset - content[..offset].rfind('\n').map_or(0, |i| i + 1) + 1;
+    ProgramError::Parse { line, column, picname, tile_id, message }
+}
+
+pub fn parse(assets: &AssetRoot) -> Result<Vec<PicData>, ProgramError> {
+    parse_str(assets.read_tile_data()?)
+}
+
+pub fn parse_str(mut content: String) -> Result<Vec<PicData>, ProgramError> {
     content += ".";
 
     match parser(&content) {
@@ -337,13 +443,60 @@ pub fn parse() -> Result<Vec<PicData>, String> {
             if remaining.trim().is_empty() | (remaining.trim() == ".") {
                 Ok(pics)
             } else {
-                Err(format!(
-                    "Parsed {} picture(s), but input remains (truncated): {:?}",
-                    pics.len(),
-                    &remaining[..std::cmp::min(80, remaining.len())]
-                ))
+                let remaining = remaining.trim_start();
+                let found = remaining.split_whitespace().next().unwrap_or_default();
+                Err(parse_error(&content, remaining, None, None, format!("cannot parse {found:?} after {} picture(s)", pics.len())))
             }
         }
-        Err(e) => Err(format!("parse error: {:?}", e)),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(parse_error(&content, e.input, e.picname(), e.tile_id(), e.message())),
+        Err(nom::Err::Incomplete(_)) => Err(parse_error(&content, "", None, None, "unexpected end of file".to_string())),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILES: &str = "Pictureabcd\n1 FFFF Field else *1 1a\n2 CFFF City u 1 Field else *2 1a start\n";
+
+    fn parse_error(content: &str) -> (usize, usize, Option<String>, Option<u8>, String) {
+        match parse_str(content.to_string()) {
+            Err(ProgramError::Parse { line, column, picname, tile_id, message }) => (line, column, picname, tile_id, message),
+            _ => panic!("{content:?} parsed"),
+        }
+    }
+
+    #[test]
+    fn parses_inline_tiles() {
+        let pics = parse_str(format!("{TILES}\nPictureefgh 3 FFFF Field else *1 1a")).unwrap();
+        assert_eq!(pics.iter().map(|pic| pic.name.as_str()).collect::<Vec<_>>(), vec!["abcd", "efgh"]);
+        let tile = &pics[0].tiles[1];
+        assert_eq!((tile.id, tile.segments.len(), tile.nums[0].num), (2, 2, 2));
+        assert!(matches!(tile.nums[0].extra_order[..], [ExtraOrderData::Start {}]));
+    }
+
+    #[test]
+    fn error_names_line_column_picture_and_tile() {
+        let (line, column, picname, tile_id, message) = parse_error(&format!("{TILES}Pictureefgh\n7 FFFX Field else *1 1a"));
+        assert_eq!((line, column), (5, 6));
+        assert_eq!((picname.as_deref(), tile_id), (Some("efgh"), Some(7)));
+        assert!(message.ends_with("in tile sides"), "{message}");
+
+        let (line, column, picname, tile_id, message) = parse_error(&format!("{TILES}Pictureefgh\n7 FFFF Field else *1 1a\n  12 FFFF Field nowhere *1 1a"));
+        assert_eq!((line, column), (6, 17));
+        assert_eq!((picname.as_deref(), tile_id), (Some("efgh"), Some(12)));
+        assert!(message.ends_with("in segment"), "{message}");
+    }
+
+    #[test]
+    fn typo_in_extra_order_is_reported_there() {
+        let (line, column, picname, tile_id, message) = parse_error(&format!("{TILES}3 FFFF Field else *1 1a stort\n"));
+        assert_eq!((line, column), (4, 25));
+        assert_eq!((picname.as_deref(), tile_id), (Some("abcd"), Some(3)));
+        assert!(message.ends_with("in extra order of tile count"), "{message}");
+
+        let (line, column, _, tile_id, message) = parse_error(&format!("{TILES}3 FFFF Field else *1 1a start; Cty 0 pennant\n"));
+        assert_eq!((line, column, tile_id), (4, 32, Some(3)));
+        assert!(message.contains("extra order"), "{message}");
+    }
+}
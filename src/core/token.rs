@@ -1,18 +1,58 @@
-use std::collections::HashMap;
+use crate::core::segment::SegmentType;
 
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Token {
     Meeple,
     BigMeeple,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum BelongingToken {
     Builder,
     Pig,
 }
 
+impl Token {
+    pub const ALL: [Token; 2] = [Token::Meeple, Token::BigMeeple];
+    /// How much the token counts toward the majority of an object.
+    pub fn strength(&self) -> u32 {
+        match self {
+            Token::Meeple => 1,
+            Token::BigMeeple => 2,
+        }
+    }
+}
+
+impl BelongingToken {
+    pub const ALL: [BelongingToken; 2] = [BelongingToken::Builder, BelongingToken::Pig];
+    pub fn can_attach(&self, typ: &SegmentType) -> bool {
+        match self {
+            BelongingToken::Builder => typ.is_road() || typ.is_city(),
+            BelongingToken::Pig => typ.is_field(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Goods {
+    Wine,
+    Grain,
+    Cloth,
+}
+
+impl Goods {
+    pub const ALL: [Goods; 3] = [Goods::Wine, Goods::Grain, Goods::Cloth];
+    pub fn from_name(name: &str) -> Option<Goods> {
+        match name {
+            "Wine" => Some(Goods::Wine),
+            "Grain" => Some(Goods::Grain),
+            "Cloth" => Some(Goods::Cloth),
+            _ => None
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PublicToken {
     Dragon,
@@ -23,6 +63,7 @@ pub enum PublicBelongingToken {
     Fairy
 }
 
+#[derive(Clone)]
 pub struct PlacedToken {
     pub token: Token,
     pub belonging: Vec<PlacedBelongingToken>,
@@ -30,6 +71,7 @@ pub struct PlacedToken {
     pub player_id: usize
 }
 
+#[derive(Clone)]
 pub struct PlacedBelongingToken {
     pub token: BelongingToken,
     pub player_id: usize
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::core::lib::*;
+use crate::core::tilepic::*;
+
+/// A mistake in a tile of `tiledata.txt` that the parser lets through.
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub picname: String,
+    pub tile_id: u8,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "picture {}, tile {}: {}", self.picname, self.tile_id, self.message)
+    }
+}
+
+/// Checks every tile of every picture and returns all the issues found.
+pub fn validate(pics: &[PicData]) -> Vec<Issue> {
+    let mut ret = Vec::new();
+    for pic in pics {
+        for tile in &pic.tiles {
+            ret.extend(validate_tile(tile).into_iter().map(|message| Issue {
+                picname: pic.name.clone(),
+                tile_id: tile.id,
+                message
+            }));
+        }
+    }
+    ret
+}
+
+fn half(dir: Dir8) -> String {
+    format!("{:?} side ({})", dir.dir, if dir.clockwise_side { "clockwise half" } else { "counterclockwise half" })
+}
+
+/// Counts the segments `read_tile_data` builds for a tile, by type, as they
+/// are added.
+#[derive(Default)]
+struct Built {
+    points: HashMap<SegmentPicType, usize>,
+    cities: usize,
+    fields: usize,
+    roads: usize,
+    rivers: usize,
+}
+
+impl Built {
+    fn count(&self, typ: SegmentPicType) -> Option<usize> {
+        match typ {
+            SegmentPicType::City => Some(self.cities),
+            SegmentPicType::Field => Some(self.fields),
+            SegmentPicType::Road => Some(self.roads),
+            SegmentPicType::River => Some(self.rivers),
+            _ => None
+        }
+    }
+    fn check_any_pos(&self, pos: &AnyPos, issues: &mut Vec<String>) {
+        if let AnyPos::Point { typ, index } = pos {
+            let count = self.points.get(typ).copied().unwrap_or(0);
+            if *index >= count {
+                issues.push(format!("{typ:?}{index} refers to a point that does not exist, there are {count}"));
+            }
+        }
+    }
+}
+
+/// The issues of one tile, as messages.
+pub fn validate_tile(tile: &TilePicData) -> Vec<String> {
+    let mut issues = Vec::new();
+    let mut built = Built::default();
+    // the area segment covering each half side, and the type of each side
+    // as the segments draw it
+    let mut covered: HashMap<Dir8, usize> = HashMap::new();
+    let mut drawn: [Option<SideType>; 4] = [None; 4];
+    let mut else_area = None;
+
+    for (i, seg) in tile.segments.iter().enumerate() {
+        let mut cover = |dirs: &[Dir8], issues: &mut Vec<String>| {
+            for &dir in dirs {
+                if let Some(other) = covered.insert(dir, i) {
+                    issues.push(format!("{} is covered by both segment {other} and segment {i}", half(dir)));
+                }
+            }
+        };
+        let halves = |dir: Dir4| [Dir8::new(dir, true), Dir8::new(dir, false)];
+        match (&seg.typ, &seg.pic) {
+            (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::OneSide { dir, .. }) => {
+                cover(&halves(*dir), &mut issues);
+                if seg.typ == SegmentPicType::City { drawn[dir.id()] = Some(SideType::City); built.cities += 1; }
+                else { built.fields += 1; }
+            }
+            (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::DoubleSide { dir, .. }) => {
+                if dir.0 == dir.1 {
+                    issues.push(format!("segment {i} spans {:?} twice", dir.0));
+                }
+                cover(&[halves(dir.0), halves(dir.1)].concat(), &mut issues);
+                if seg.typ == SegmentPicType::City {
+                    drawn[dir.0.id()] = Some(SideType::City);
+                    drawn[dir.1.id()] = Some(SideType::City);
+                    built.cities += 1;
+                }
+                else { built.fields += 1; }
+            }
+            (SegmentPicType::Road, SegmentPicData::OneSide { dir, .. }) => {
+                cover(&halves(*dir), &mut issues);
+                drawn[dir.id()] = Some(SideType::Road);
+                built.fields += 2;
+                built.roads += 1;
+            }
+            (SegmentPicType::Junction | SegmentPicType::Roundabout | SegmentPicType::Bridge | SegmentPicType::Feature, SegmentPicData::Point { .. }) => {
+                *built.points.entry(seg.typ).or_insert(0) += 1;
+            }
+            (SegmentPicType::Road | SegmentPicType::River | SegmentPicType::Cut, SegmentPicData::Line { pos, .. }) => {
+                for end in [&pos.0, &pos.1] {
+                    built.check_any_pos(end, &mut issues);
+                    if let AnyPos::Dir { dir } = end {
+                        match seg.typ {
+                            SegmentPicType::Road => drawn[dir.id()] = Some(SideType::Road),
+                            SegmentPicType::River => drawn[dir.id()] = Some(SideType::River),
+                            _ => ()
+                        }
+                    }
+                }
+                match seg.typ {
+                    SegmentPicType::Road => built.roads += 1,
+                    SegmentPicType::River => built.rivers += 1,
+                    _ => ()
+                }
+            }
+            (SegmentPicType::Tunnel, SegmentPicData::Tunnel { road }) => {
+                for id in [road.0, road.1] {
+                    if id >= built.roads {
+                        issues.push(format!("tunnel refers to road {id}, there are {}", built.roads));
+                    }
+                }
+                if road.0 == road.1 {
+                    issues.push(format!("tunnel joins road {} to itself", road.0));
+                }
+                else if road.0 < built.roads && road.1 < built.roads {
+                    built.roads -= 1;
+                }
+            }
+            (SegmentPicType::City | SegmentPicType::Field, SegmentPicData::Else { road_sides, adj_city }) => {
+                if else_area.is_some() {
+                    issues.push(format!("segment {i} is a second else segment"));
+                }
+                else_area = Some(seg.typ);
+                for &city in adj_city {
+                    if city as usize >= built.cities {
+                        issues.push(format!("else segment is adjacent to city {city}, there are {}", built.cities));
+                    }
+                }
+                let parts = road_sides.len().max(1);
+                if seg.typ == SegmentPicType::City { built.cities += parts; }
+                else { built.fields += parts; }
+            }
+            (typ, pic) => issues.push(format!("segment {i} is a {typ:?} drawn as {pic:?}"))
+        }
+    }
+
+    if else_area.is_none() {
+        for dir in Dir8::ALL {
+            if !covered.contains_key(&dir) {
+                issues.push(format!("{} is not covered by any segment", half(dir)));
+            }
+        }
+    }
+    for (i, side) in tile.sides.iter().enumerate() {
+        let expected = drawn[i].unwrap_or(match else_area {
+            Some(SegmentPicType::City) => SideType::City,
+            _ => SideType::Field
+        });
+        if *side != expected {
+            issues.push(format!("{:?} side is declared {side:?} but the segments make it {expected:?}", Dir4::from_id(i)));
+        }
+    }
+
+    for num in &tile.nums {
+        if Extension::try_from(num.packname).is_err() {
+            issues.push(format!("unknown pack {}{}", num.packname.0, num.packname.1));
+        }
+        for extra in &num.extra_order {
+            let (typ, id) = match extra {
+                ExtraOrderData::Feature { typ, id, .. } | ExtraOrderData::Hint { typ, id, .. } | ExtraOrderData::RoadWidth { typ, id, .. } => (*typ, *id),
+                ExtraOrderData::Addable { pos: Some(pos), .. } => {
+                    built.check_any_pos(pos, &mut issues);
+                    continue;
+                }
+                _ => continue
+            };
+            if let Some(count) = built.count(typ) && id as usize >= count {
+                issues.push(format!("extra order refers to {typ:?} {id}, there are {count}"));
+            }
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(tile: &str) -> Vec<String> {
+        let pics = parse_str(format!("Pictureabcd\n{tile}")).unwrap();
+        validate_tile(&pics[0].tiles[0])
+    }
+
+    #[test]
+    fn sound_tile_has_no_issues() {
+        assert!(issues("0 CRFR City u 12 Road l-r 0 Field else *1 1a City 0 pennant").is_empty());
+    }
+
+    #[test]
+    fn sides_must_match_segments() {
+        assert_eq!(issues("0 FFFF City u 12 Field else *1 1a"), vec!["Up side is declared Field but the segments make it City"]);
+    }
+
+    #[test]
+    fn half_sides_are_covered_once() {
+        assert_eq!(issues("0 CFFF City u 12 Field u-d 0 Field else *1 1a"), vec![
+            "Up side (clockwise half) is covered by both segment 0 and segment 1",
+            "Up side (counterclockwise half) is covered by both segment 0 and segment 1",
+        ]);
+        let mut missing = issues("0 CFFF City u 12 *1 1a");
+        missing.sort();
+        assert_eq!(missing.len(), 6);
+        assert_eq!(missing[0], "Down side (clockwise half) is not covered by any segment");
+    }
+
+    #[test]
+    fn points_must_exist() {
+        assert_eq!(issues("0 FRFF Junction 32,32 Road r-Junction1 0 Field else *1 1a"),
+            vec!["Junction1 refers to a point that does not exist, there are 1"]);
+    }
+
+    #[test]
+    fn tunnels_join_existing_roads() {
+        assert_eq!(issues("0 FRFR Road l-r 0 Tunnel Road0 Road3 Field else *1 1a"), vec!["tunnel refers to road 3, there are 1"]);
+    }
+
+    #[test]
+    fn packs_must_be_known() {
+        assert_eq!(issues("0 CFFF City u 12 Field else *1 9z"), vec!["unknown pack 9z"]);
+    }
+}
@@ -1,8 +1,15 @@
 pub mod core;
+use std::path::Path;
+use std::process::ExitCode;
 use genawaiter::{rc::Gen, GeneratorState};
+use crate::core::asset::AssetRoot;
 
-fn main() {
-    let v = crate::core::tilepic::parse().unwrap();
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("validate") {
+        return validate(args.get(2).map(Path::new));
+    }
+    let v = crate::core::tilepic::parse(&AssetRoot::resolve(None)).unwrap();
     // let mut l = proceed();
     // if let GeneratorState::Yielded(x) = l.resume_with(2) {
     //     println!("{x}");
@@ -10,10 +17,35 @@ fn main() {
     // if let GeneratorState::Yielded(x) = l.resume_with(3) {
     //     println!("{x}");
     // }
+    ExitCode::SUCCESS
+}
+
+/// `validate [asset dir]`: parses `tiledata.txt` and lists every issue found
+/// in it. Fails if there is any.
+fn validate(dir: Option<&Path>) -> ExitCode {
+    let pics = match crate::core::tilepic::parse(&AssetRoot::resolve(dir)) {
+        Ok(pics) => pics,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let issues = crate::core::validate::validate(&pics);
+    for issue in &issues {
+        eprintln!("{issue}");
+    }
+    if issues.is_empty() {
+        println!("{} picture(s), {} tile(s): no issues", pics.len(), pics.iter().map(|pic| pic.tiles.len()).sum::<usize>());
+        ExitCode::SUCCESS
+    }
+    else {
+        eprintln!("{} issue(s)", issues.len());
+        ExitCode::FAILURE
+    }
 }
 
 fn proceed() -> Gen<i32, i32, impl Future<Output=()>> {
     Gen::new(|co| async move {
         let x = co.yield_(1).await;
     })
-}
\ No newline at end of file
+}